@@ -0,0 +1,110 @@
+//! Cost of the synthesis per output sample. Criterion reports the throughput in samples, or in
+//! voice samples for the voice counts, so a regression shows up as fewer elements per second.
+//!
+//! Run with `cargo bench`, or `cargo bench -- oversampling` for a single group.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use drum_synth::{DrumEngine, Patch};
+
+/// A small host buffer, where dropouts show up first
+const BUFFER_SIZE: usize = 64;
+
+/// A new hit starts this often so the voice never goes quiet, in buffers
+const RETRIGGER_BUFFERS: usize = 64;
+
+/// An engine playing the default patch, `oversampling` is the factor
+fn engine(sample_rate: f32, oversampling: usize) -> DrumEngine {
+    let json = format!(
+        r#"{{"name": "Bench", "state_version": 4, "params": {{"oversampling": "{oversampling}x"}}}}"#
+    );
+    let mut engine = DrumEngine::new(sample_rate);
+    engine.set_patch(&Patch::from_json(&json).unwrap());
+
+    engine
+}
+
+/// Render `buffers` buffers, retriggering every voice regularly
+fn run(voices: &mut [DrumEngine], output: &mut [f32], buffers: usize) {
+    for buffer in 0..buffers {
+        for voice in voices.iter_mut() {
+            if buffer % RETRIGGER_BUFFERS == 0 {
+                voice.trigger(38, 1.0);
+            }
+            voice.render(output);
+            black_box(&output);
+        }
+    }
+}
+
+fn sample_rates(c: &mut Criterion) {
+    let mut group = c.benchmark_group("sample_rate");
+    group.throughput(Throughput::Elements(
+        (BUFFER_SIZE * RETRIGGER_BUFFERS) as u64,
+    ));
+    for sample_rate in [44100.0, 48000.0, 96000.0, 192000.0] {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(sample_rate),
+            &sample_rate,
+            |b, &sample_rate| {
+                let mut voices = [engine(sample_rate, 1)];
+                let mut output = [0.0; BUFFER_SIZE];
+                b.iter(|| run(&mut voices, &mut output, RETRIGGER_BUFFERS));
+            },
+        );
+    }
+    group.finish();
+}
+
+fn oversampling(c: &mut Criterion) {
+    let mut group = c.benchmark_group("oversampling");
+    group.throughput(Throughput::Elements(
+        (BUFFER_SIZE * RETRIGGER_BUFFERS) as u64,
+    ));
+    for factor in [1, 2, 4, 8] {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(format!("{factor}x")),
+            &factor,
+            |b, &factor| {
+                let mut voices = [engine(48000.0, factor)];
+                let mut output = [0.0; BUFFER_SIZE];
+                b.iter(|| run(&mut voices, &mut output, RETRIGGER_BUFFERS));
+            },
+        );
+    }
+    group.finish();
+}
+
+/// The synth is monophonic, so more voices are independent instances like in a large kit. The
+/// throughput is per voice sample, which gives the cost of a single voice.
+fn voices(c: &mut Criterion) {
+    let mut group = c.benchmark_group("voices");
+    for count in [1, 4, 16] {
+        group.throughput(Throughput::Elements(
+            (BUFFER_SIZE * RETRIGGER_BUFFERS * count) as u64,
+        ));
+        group.bench_with_input(BenchmarkId::from_parameter(count), &count, |b, &count| {
+            let mut voices: Vec<_> = (0..count).map(|_| engine(48000.0, 1)).collect();
+            let mut output = [0.0; BUFFER_SIZE];
+            b.iter(|| run(&mut voices, &mut output, RETRIGGER_BUFFERS));
+        });
+    }
+    group.finish();
+}
+
+/// A voice whose hit has died down, which should cost next to nothing
+fn idle(c: &mut Criterion) {
+    let mut group = c.benchmark_group("idle");
+    group.throughput(Throughput::Elements(BUFFER_SIZE as u64));
+    group.bench_function("silent", |b| {
+        let mut voice = engine(48000.0, 1);
+        let mut output = [0.0; BUFFER_SIZE];
+        b.iter(|| {
+            voice.render(&mut output);
+            black_box(&output);
+        });
+    });
+    group.finish();
+}
+
+criterion_group!(benches, sample_rates, oversampling, voices, idle);
+criterion_main!(benches);
@@ -0,0 +1,269 @@
+//! Command line tools for the standalone binary. Everything that isn't one of these commands is
+//! passed on to the standalone host.
+
+use std::path::Path;
+use std::process::ExitCode;
+
+use crate::export::{self, ExportSettings};
+use crate::presets::{Preset, PresetManager};
+use crate::render::{self, RenderSettings, WavFormat};
+
+const PRESET_USAGE: &str = "Usage:
+  drum_synth preset list                   List the factory and user presets
+  drum_synth preset export <name> <file>   Write a preset to a JSON file
+  drum_synth preset import <file>          Add a JSON preset file to the user presets";
+
+const RENDER_USAGE: &str = "Usage:
+  drum_synth render [options] -o <file>    Render a single hit to a WAV file
+
+Options:
+  --preset <file or name>   Preset file or the name of a factory or user preset, defaults to the
+                            default patch
+  --note <note>             MIDI note number, defaults to 38
+  --velocity <velocity>     MIDI velocity from 1 to 127, defaults to 127
+  --length <time>           Length of the file, for example 2s or 500ms, defaults to 2s
+  --gate <time>             Time until the note is released, defaults to 100ms
+  --rate <rate>             Sample rate, defaults to 48000
+  --bits <bits>             16, 24 or 32 (float), defaults to 24";
+
+const EXPORT_USAGE: &str = "Usage:
+  drum_synth export [options] -o <dir>     Render a sample pack with SFZ and Decent Sampler mappings
+
+Options:
+  --preset <file or name>   Preset file or the name of a factory or user preset, defaults to the
+                            default patch
+  --note <note>             MIDI note number, defaults to 38
+  --layers <layers>         Number of velocity layers from 1 to 127, defaults to 4
+  --round-robins <count>    Number of round robins per layer, defaults to 4
+  --length <time>           Longest length of a hit before trimming the silence, defaults to 4s
+  --gate <time>             Time until the note is released, defaults to 100ms
+  --rate <rate>             Sample rate, defaults to 48000
+  --bits <bits>             16, 24 or 32 (float), defaults to 24
+  --normalize               Bring the loudest hit up to just below 0 dBFS, the layers keep their
+                            levels relative to each other";
+
+/// Run a command line tool if the arguments ask for one. Returns `None` if the standalone host
+/// should start instead.
+pub fn run(args: &[String]) -> Option<ExitCode> {
+    match args.get(1).map(String::as_str) {
+        Some("preset") => Some(report(preset_command(&args[2..]))),
+        Some("render") => Some(report(render_command(&args[2..]))),
+        Some("export") => Some(report(export_command(&args[2..]))),
+        _ => None,
+    }
+}
+
+fn report(result: Result<(), String>) -> ExitCode {
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn preset_command(args: &[String]) -> Result<(), String> {
+    let mut presets = PresetManager::new();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    match args.as_slice() {
+        ["list"] => {
+            for entry in presets.entries() {
+                let kind = if entry.factory { "factory" } else { "user" };
+                println!("{:<8} {}", kind, entry.preset.name);
+            }
+
+            Ok(())
+        }
+        ["export", name, file] => {
+            let entry = presets
+                .index_of(name)
+                .and_then(|index| presets.get(index))
+                .ok_or_else(|| format!("No preset named '{name}'"))?;
+            entry
+                .preset
+                .save(Path::new(file))
+                .map_err(|err| format!("Could not write '{file}': {err}"))
+        }
+        ["import", file] => {
+            let index = presets
+                .import_preset(Path::new(file))
+                .map_err(|err| format!("Could not import '{file}': {err}"))?;
+            let name = &presets.entries()[index].preset.name;
+            println!("Imported '{name}'");
+
+            Ok(())
+        }
+        _ => Err(PRESET_USAGE.to_owned()),
+    }
+}
+
+/// Load a preset from a file, or find a factory or user preset with that name
+fn find_preset(name_or_file: &str) -> Result<Preset, String> {
+    let path = Path::new(name_or_file);
+    if path.is_file() {
+        return Preset::load(path).map_err(|err| format!("Could not load '{name_or_file}': {err}"));
+    }
+
+    let presets = PresetManager::new();
+    presets
+        .index_of(name_or_file)
+        .and_then(|index| presets.get(index))
+        .map(|entry| entry.preset.clone())
+        .ok_or_else(|| format!("No preset file or preset named '{name_or_file}'"))
+}
+
+/// Parse a time like `2s`, `500ms` or `1.5`, plain numbers are seconds
+fn parse_time(time: &str) -> Option<f32> {
+    let seconds = match time.strip_suffix("ms") {
+        Some(millis) => millis.parse::<f32>().ok()? / 1000.0,
+        None => time.strip_suffix('s').unwrap_or(time).parse().ok()?,
+    };
+
+    (seconds >= 0.0).then_some(seconds)
+}
+
+fn parse_option<T>(
+    option: &str,
+    value: &str,
+    parse: impl FnOnce(&str) -> Option<T>,
+) -> Result<T, String> {
+    parse(value).ok_or_else(|| format!("Invalid value for {option}: '{value}'"))
+}
+
+fn render_command(args: &[String]) -> Result<(), String> {
+    let mut preset = None;
+    let mut output = None;
+    let mut settings = RenderSettings {
+        note: 38,
+        velocity: 1.0,
+        gate: 0.1,
+        length: 2.0,
+        sample_rate: 48000.0,
+        seed: 0,
+    };
+    let mut format = WavFormat::Int24;
+
+    let mut args = args.iter().map(String::as_str);
+    while let Some(option) = args.next() {
+        let value = args.next().ok_or_else(|| RENDER_USAGE.to_owned())?;
+        match option {
+            "--preset" => preset = Some(find_preset(value)?),
+            "-o" | "--output" => output = Some(value),
+            "--note" => {
+                settings.note = parse_option(option, value, |value| {
+                    value.parse().ok().filter(|note| *note < 128)
+                })?
+            }
+            "--velocity" => {
+                let velocity: u8 = parse_option(option, value, |value| {
+                    value
+                        .parse()
+                        .ok()
+                        .filter(|velocity| (1..128).contains(velocity))
+                })?;
+                settings.velocity = velocity as f32 / 127.0;
+            }
+            "--length" => settings.length = parse_option(option, value, parse_time)?,
+            "--gate" => settings.gate = parse_option(option, value, parse_time)?,
+            "--rate" => {
+                settings.sample_rate = parse_option(option, value, |value| {
+                    value
+                        .parse()
+                        .ok()
+                        .filter(|rate| (8000.0..=384000.0).contains(rate))
+                })?
+            }
+            "--bits" => {
+                format = parse_option(option, value, |value| {
+                    value.parse().ok().and_then(WavFormat::from_bits)
+                })?
+            }
+            _ => return Err(RENDER_USAGE.to_owned()),
+        }
+    }
+    let output = output.ok_or_else(|| RENDER_USAGE.to_owned())?;
+
+    let samples = render::render_note(preset.as_ref(), &settings);
+    render::write_wav(
+        Path::new(output),
+        &samples,
+        settings.sample_rate as u32,
+        format,
+    )
+    .map_err(|err| format!("Could not write '{output}': {err}"))
+}
+
+fn export_command(args: &[String]) -> Result<(), String> {
+    let mut preset = None;
+    let mut output = None;
+    let mut settings = ExportSettings {
+        note: 38,
+        velocity_layers: 4,
+        round_robins: 4,
+        gate: 0.1,
+        length: 4.0,
+        sample_rate: 48000.0,
+        format: WavFormat::Int24,
+        normalize: false,
+    };
+
+    let mut args = args.iter().map(String::as_str);
+    while let Some(option) = args.next() {
+        if option == "--normalize" {
+            settings.normalize = true;
+            continue;
+        }
+
+        let value = args.next().ok_or_else(|| EXPORT_USAGE.to_owned())?;
+        match option {
+            "--preset" => preset = Some(find_preset(value)?),
+            "-o" | "--output" => output = Some(value),
+            "--note" => {
+                settings.note = parse_option(option, value, |value| {
+                    value.parse().ok().filter(|note| *note < 128)
+                })?
+            }
+            "--layers" => {
+                settings.velocity_layers = parse_option(option, value, |value| {
+                    value
+                        .parse()
+                        .ok()
+                        .filter(|layers| (1..128).contains(layers))
+                })?
+            }
+            "--round-robins" => {
+                settings.round_robins = parse_option(option, value, |value| {
+                    value.parse().ok().filter(|count| *count > 0)
+                })?
+            }
+            "--length" => settings.length = parse_option(option, value, parse_time)?,
+            "--gate" => settings.gate = parse_option(option, value, parse_time)?,
+            "--rate" => {
+                settings.sample_rate = parse_option(option, value, |value| {
+                    value
+                        .parse()
+                        .ok()
+                        .filter(|rate| (8000.0..=384000.0).contains(rate))
+                })?
+            }
+            "--bits" => {
+                settings.format = parse_option(option, value, |value| {
+                    value.parse().ok().and_then(WavFormat::from_bits)
+                })?
+            }
+            _ => return Err(EXPORT_USAGE.to_owned()),
+        }
+    }
+    let output = output.ok_or_else(|| EXPORT_USAGE.to_owned())?;
+
+    let name = preset
+        .as_ref()
+        .map_or_else(|| String::from("Default"), |preset| preset.name.clone());
+    let count = export::export_pack(preset.as_ref(), &name, &settings, Path::new(output))
+        .map_err(|err| format!("Could not write the sample pack to '{output}': {err}"))?;
+    println!("Wrote {count} samples to '{output}'");
+
+    Ok(())
+}
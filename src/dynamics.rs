@@ -0,0 +1,426 @@
+use nih_plug::util::{db_to_gain, gain_to_db};
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+
+/// Lookahead used by the limiter to catch peaks before they reach the output
+const LIMITER_LOOKAHEAD_SECONDS: f32 = 0.0015;
+
+/// Release time of the limiter's gain reduction
+const LIMITER_RELEASE_SECONDS: f32 = 0.05;
+
+/// Number of taps per phase in the limiter's true-peak interpolator
+const TRUE_PEAK_TAPS: usize = 12;
+
+/// Oversampling factor used for true-peak detection
+const TRUE_PEAK_PHASES: usize = 4;
+
+/// Samples on either side of a peak the limiter holds its gain for, so the output's
+/// interpolated values around the peak are limited as well as the peak's own sample
+const LIMITER_HOLD: usize = TRUE_PEAK_TAPS / 2;
+
+/// Maximum boost or cut the transient shaper will apply
+const SHAPER_MAX_GAIN_DB: f32 = 24.0;
+
+/// One-pole smoothing coefficient for a given time constant in seconds
+fn time_to_coeff(time: f32, sample_rate: f32) -> f32 {
+    if time <= 0.0 {
+        0.0
+    } else {
+        (-1.0 / (time * sample_rate)).exp()
+    }
+}
+
+/// Peak envelope follower with separate attack and release
+struct EnvelopeFollower {
+    attack_coeff: f32,
+    release_coeff: f32,
+    level: f32,
+}
+
+impl EnvelopeFollower {
+    fn new() -> Self {
+        Self {
+            attack_coeff: 0.0,
+            release_coeff: 0.0,
+            level: 0.0,
+        }
+    }
+
+    fn set_times(&mut self, attack: f32, release: f32, sample_rate: f32) {
+        self.attack_coeff = time_to_coeff(attack, sample_rate);
+        self.release_coeff = time_to_coeff(release, sample_rate);
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        let input = input.abs();
+        let coeff = if input > self.level { self.attack_coeff } else { self.release_coeff };
+        self.level = input + coeff * (self.level - input);
+        self.level
+    }
+
+    fn reset(&mut self) {
+        self.level = 0.0;
+    }
+}
+
+/// Differential envelope transient shaper
+///
+/// The attack portion is detected as the difference between a fast and a slow attacking
+/// follower, the sustain portion as the difference between a slow and a fast releasing one.
+pub(crate) struct TransientShaper {
+    attack_fast: EnvelopeFollower,
+    attack_slow: EnvelopeFollower,
+    sustain_fast: EnvelopeFollower,
+    sustain_slow: EnvelopeFollower,
+    attack_amount: f32,
+    sustain_amount: f32,
+}
+
+impl TransientShaper {
+    pub fn new() -> Self {
+        Self {
+            attack_fast: EnvelopeFollower::new(),
+            attack_slow: EnvelopeFollower::new(),
+            sustain_fast: EnvelopeFollower::new(),
+            sustain_slow: EnvelopeFollower::new(),
+            attack_amount: 0.0,
+            sustain_amount: 0.0,
+        }
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.attack_fast.set_times(0.0005, 0.05, sample_rate);
+        self.attack_slow.set_times(0.02, 0.05, sample_rate);
+        self.sustain_fast.set_times(0.0005, 0.02, sample_rate);
+        self.sustain_slow.set_times(0.0005, 0.3, sample_rate);
+    }
+
+    /// attack, sustain: -1.0 to 1.0 (cut to boost)
+    pub fn set_parameters(&mut self, attack: f32, sustain: f32) {
+        self.attack_amount = attack;
+        self.sustain_amount = sustain;
+    }
+
+    pub fn process(&mut self, input: f32) -> f32 {
+        let attack_fast = self.attack_fast.process(input);
+        let attack_slow = self.attack_slow.process(input);
+        let sustain_fast = self.sustain_fast.process(input);
+        let sustain_slow = self.sustain_slow.process(input);
+
+        // Skip the dB math entirely when the shaper is neutral
+        if self.attack_amount == 0.0 && self.sustain_amount == 0.0 {
+            return input;
+        }
+
+        let transient = (gain_to_db(attack_fast) - gain_to_db(attack_slow)).max(0.0);
+        let tail = (gain_to_db(sustain_slow) - gain_to_db(sustain_fast)).max(0.0);
+        let gain_db = (self.attack_amount * transient + self.sustain_amount * tail)
+            .clamp(-SHAPER_MAX_GAIN_DB, SHAPER_MAX_GAIN_DB);
+
+        input * db_to_gain(gain_db)
+    }
+
+    pub fn reset(&mut self) {
+        self.attack_fast.reset();
+        self.attack_slow.reset();
+        self.sustain_fast.reset();
+        self.sustain_slow.reset();
+    }
+}
+
+/// Feed-forward drum bus compressor with a soft knee and parallel mix
+pub(crate) struct Compressor {
+    detector: EnvelopeFollower,
+    threshold: f32,
+    ratio: f32,
+    knee: f32,
+    makeup: f32,
+    mix: f32,
+    sample_rate: f32,
+}
+
+impl Compressor {
+    pub fn new() -> Self {
+        Self {
+            detector: EnvelopeFollower::new(),
+            threshold: 0.0,
+            ratio: 1.0,
+            knee: 6.0,
+            makeup: 1.0,
+            mix: 1.0,
+            sample_rate: 44100.0,
+        }
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+    }
+
+    /// threshold, makeup: dB
+    /// attack, release: seconds
+    /// mix: 0.0-1.0 (dry to fully compressed)
+    pub fn set_parameters(&mut self, threshold: f32, ratio: f32, attack: f32, release: f32, makeup: f32, mix: f32) {
+        self.threshold = threshold;
+        self.ratio = ratio.max(1.0);
+        self.makeup = db_to_gain(makeup);
+        self.mix = mix;
+        self.detector.set_times(attack, release, self.sample_rate);
+    }
+
+    /// Static gain computer, returns the gain reduction in dB (zero or negative)
+    fn gain_reduction(&self, level_db: f32) -> f32 {
+        let overshoot = level_db - self.threshold;
+        let slope = 1.0 / self.ratio - 1.0;
+
+        if 2.0 * overshoot < -self.knee {
+            0.0
+        } else if 2.0 * overshoot > self.knee {
+            slope * overshoot
+        } else {
+            let x = overshoot + self.knee / 2.0;
+            slope * x * x / (2.0 * self.knee)
+        }
+    }
+
+    pub fn process(&mut self, input: f32) -> f32 {
+        let level = self.detector.process(input);
+
+        // A 1:1 ratio is a bypass, but keep the detector running so engaging it doesn't jump
+        if self.ratio <= 1.0 {
+            return input;
+        }
+
+        let reduction = db_to_gain(self.gain_reduction(gain_to_db(level)));
+        let compressed = input * reduction * self.makeup;
+        input + (compressed - input) * self.mix
+    }
+
+    pub fn reset(&mut self) {
+        self.detector.reset();
+    }
+}
+
+/// Lookahead brickwall limiter with true-peak detection
+///
+/// Peaks are measured on a 4x interpolated version of the signal so inter-sample overs are
+/// caught as well. The lookahead delay only applies while the limiter is enabled, so switching
+/// it changes the reported latency.
+pub(crate) struct Limiter {
+    enabled: bool,
+    ceiling: f32,
+    gain: f32,
+    release_coeff: f32,
+
+    /// Windowed sinc interpolation filter, one row per phase
+    interpolator: [[f32; TRUE_PEAK_TAPS]; TRUE_PEAK_PHASES],
+    history: [f32; TRUE_PEAK_TAPS],
+    history_pos: usize,
+
+    /// Length of the lookahead window in samples
+    lookahead: usize,
+    /// Sample counter for the lookahead window
+    position: usize,
+    /// The required gains inside the hold window that can still become its minimum, as
+    /// `(position, gain)`. The gains increase from front to back, so the front is the minimum.
+    gain_window: VecDeque<(usize, f32)>,
+    /// The last `lookahead + 1` held gains, their average ramps the gain down linearly so it
+    /// reaches a peak's gain right as the peak is output
+    ramp: Vec<f32>,
+    ramp_pos: usize,
+    ramp_sum: f64,
+
+    delay_buffer: Vec<f32>,
+    delay_pos: usize,
+}
+
+impl Limiter {
+    pub fn new() -> Self {
+        let mut interpolator = [[0.0; TRUE_PEAK_TAPS]; TRUE_PEAK_PHASES];
+        let center = (TRUE_PEAK_TAPS / 2) as f32;
+        for (phase, row) in interpolator.iter_mut().enumerate() {
+            for (tap, coeff) in row.iter_mut().enumerate() {
+                let t = center - tap as f32 - phase as f32 / TRUE_PEAK_PHASES as f32;
+                let sinc = if t == 0.0 { 1.0 } else { (PI * t).sin() / (PI * t) };
+                let window = 0.5 * (1.0 + (PI * t / center).cos());
+                *coeff = sinc * window;
+            }
+        }
+
+        Self {
+            enabled: true,
+            ceiling: 1.0,
+            gain: 1.0,
+            release_coeff: 0.0,
+            interpolator,
+            history: [0.0; TRUE_PEAK_TAPS],
+            history_pos: 0,
+            lookahead: 1,
+            position: 0,
+            gain_window: VecDeque::new(),
+            ramp: vec![1.0; 2],
+            ramp_pos: 0,
+            ramp_sum: 2.0,
+            delay_buffer: vec![0.0; 1],
+            delay_pos: 0,
+        }
+    }
+
+    /// Allocates the lookahead buffers, must not be called from the audio thread
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        let lookahead = ((LIMITER_LOOKAHEAD_SECONDS * sample_rate) as usize).max(1);
+        self.lookahead = lookahead;
+        self.position = 0;
+        self.gain_window = VecDeque::with_capacity(lookahead + 2 * LIMITER_HOLD + 1);
+        self.ramp = vec![1.0; lookahead + 1];
+        self.ramp_pos = 0;
+        self.ramp_sum = self.ramp.len() as f64;
+        // The interpolator's output lags its input by half its taps. A peak's required gain has
+        // fully ramped in after the lookahead, and is then held for `LIMITER_HOLD` samples on
+        // either side of the peak.
+        self.delay_buffer = vec![0.0; lookahead + LIMITER_HOLD + TRUE_PEAK_TAPS / 2];
+        self.delay_pos = 0;
+
+        self.release_coeff = time_to_coeff(LIMITER_RELEASE_SECONDS, sample_rate);
+    }
+
+    /// Latency introduced by the lookahead and the true-peak interpolator, in samples. Zero while
+    /// the limiter is disabled.
+    pub fn latency(&self) -> u32 {
+        if self.enabled {
+            self.delay_buffer.len() as u32
+        } else {
+            0
+        }
+    }
+
+    /// Changes the latency, so this should only be switched between blocks
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// ceiling: dB
+    pub fn set_ceiling(&mut self, ceiling: f32) {
+        self.ceiling = db_to_gain(ceiling);
+    }
+
+    /// Highest interpolated level between the sample `TRUE_PEAK_TAPS / 2` samples ago and the
+    /// one after it. The first phase is that sample itself.
+    fn true_peak(&mut self, input: f32) -> f32 {
+        self.history[self.history_pos] = input;
+
+        let mut peak = 0.0f32;
+        for row in &self.interpolator {
+            let mut sum = 0.0;
+            for (tap, coeff) in row.iter().enumerate() {
+                let index = (self.history_pos + TRUE_PEAK_TAPS - tap) % TRUE_PEAK_TAPS;
+                sum += self.history[index] * coeff;
+            }
+            peak = peak.max(sum.abs());
+        }
+
+        self.history_pos = (self.history_pos + 1) % TRUE_PEAK_TAPS;
+        peak
+    }
+
+    pub fn process(&mut self, input: f32) -> f32 {
+        let peak = self.true_peak(input);
+
+        // Delay the signal so the gain can come down before the peak arrives. The delay line keeps
+        // running while disabled so enabling the limiter doesn't start with stale audio.
+        let delayed = self.delay_buffer[self.delay_pos];
+        self.delay_buffer[self.delay_pos] = input;
+        self.delay_pos = (self.delay_pos + 1) % self.delay_buffer.len();
+
+        if !self.enabled {
+            self.clear_gain();
+            return input;
+        }
+
+        // Sliding minimum over the lookahead and the hold on either side. A gain that's at least
+        // as high as a newer one can never be the minimum again, so it's dropped.
+        let required = if peak > self.ceiling { self.ceiling / peak } else { 1.0 };
+        while self.gain_window.back().is_some_and(|&(_, gain)| gain >= required) {
+            self.gain_window.pop_back();
+        }
+        self.gain_window.push_back((self.position, required));
+        let window = self.lookahead + 2 * LIMITER_HOLD + 1;
+        while self
+            .gain_window
+            .front()
+            .is_some_and(|&(position, _)| position + window <= self.position)
+        {
+            self.gain_window.pop_front();
+        }
+        self.position = self.position.wrapping_add(1);
+        let held = self.gain_window.front().map_or(1.0, |&(_, gain)| gain);
+
+        // Every held gain in the average is at most the peak's by the time it's output, so the
+        // ramp gets there exactly. Releasing is smoothed on top of that.
+        self.ramp_sum += (held - self.ramp[self.ramp_pos]) as f64;
+        self.ramp[self.ramp_pos] = held;
+        self.ramp_pos = (self.ramp_pos + 1) % self.ramp.len();
+        let target = (self.ramp_sum / self.ramp.len() as f64) as f32;
+        self.gain = if target < self.gain {
+            target
+        } else {
+            target + self.release_coeff * (self.gain - target)
+        };
+
+        let output = delayed * self.gain;
+        debug_assert!(output.abs() <= self.ceiling * 1.001, "limiter overshoot: {output}");
+        output
+    }
+
+    fn clear_gain(&mut self) {
+        self.gain = 1.0;
+        self.gain_window.clear();
+        self.ramp.fill(1.0);
+        self.ramp_sum = self.ramp.len() as f64;
+    }
+
+    pub fn reset(&mut self) {
+        self.clear_gain();
+        self.history = [0.0; TRUE_PEAK_TAPS];
+        self.history_pos = 0;
+        self.position = 0;
+        self.delay_buffer.fill(0.0);
+        self.delay_pos = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::noise::Pcg32;
+
+    /// Decaying bursts far above the ceiling: noise, sines up to Nyquist whose peaks fall
+    /// between samples, and single clicks
+    #[test]
+    fn limiter_keeps_true_peaks_below_ceiling() {
+        let mut limiter = Limiter::new();
+        limiter.set_sample_rate(48000.0);
+        limiter.set_ceiling(-1.0);
+        // Only its interpolator is used, to measure the output
+        let mut meter = Limiter::new();
+        let mut rng = Pcg32::new(3);
+
+        let mut peak = 0.0f32;
+        for burst in 0..60 {
+            let amplitude = 2.0 + 14.0 * rng.next_f32();
+            let freq = 0.5 * rng.next_f32();
+            for index in 0..2000 {
+                let time = index as f32;
+                let signal = match burst % 3 {
+                    0 => rng.next_bipolar(),
+                    1 => (2.0 * PI * freq * time).sin(),
+                    _ => (index == 0) as u8 as f32,
+                };
+                let input = signal * amplitude * (-time / 200.0).exp();
+                peak = peak.max(meter.true_peak(limiter.process(input)));
+            }
+        }
+
+        // Up to rounding in the interpolation
+        assert!(peak <= limiter.ceiling * 1.00001, "{} dB over", gain_to_db(peak / limiter.ceiling));
+    }
+}
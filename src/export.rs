@@ -0,0 +1,244 @@
+//! Sample packs: a patch rendered at several velocities and round robins, along with mapping
+//! files for SFZ and Decent Sampler
+
+use nih_plug::util;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::presets::Preset;
+use crate::render::{self, sample_base_name, trim_silence, RenderSettings, WavFormat};
+
+/// Peak level of the loudest hit when normalizing
+const NORMALIZE_PEAK_DB: f32 = -0.3;
+
+pub(crate) struct ExportSettings {
+    pub note: u8,
+    /// From 1 to 127, the layers split the velocity range evenly
+    pub velocity_layers: u32,
+    pub round_robins: u32,
+    /// Time until the note off in seconds
+    pub gate: f32,
+    /// Longest a hit can be before trimming, in seconds
+    pub length: f32,
+    pub sample_rate: f32,
+    pub format: WavFormat,
+    /// Raise or lower the whole pack so the loudest hit peaks at `NORMALIZE_PEAK_DB`. The hits
+    /// keep their levels relative to each other so the velocity layers still get louder.
+    pub normalize: bool,
+}
+
+/// A rendered hit and where it goes in the mapping
+struct PackSample {
+    file_name: String,
+    low_velocity: u8,
+    high_velocity: u8,
+    /// Starts at 1, like the mapping formats count them
+    round_robin: u32,
+    samples: Vec<f32>,
+}
+
+/// MIDI velocity range covered by a layer, the layer is rendered at the top of its range
+fn velocity_range(layer: u32, layers: u32) -> (u8, u8) {
+    let high = |layer: u32| (127 * (layer + 1) / layers) as u8;
+    let low = if layer == 0 { 1 } else { high(layer - 1) + 1 };
+
+    (low, high(layer))
+}
+
+fn render_pack(preset: Option<&Preset>, name: &str, settings: &ExportSettings) -> Vec<PackSample> {
+    let base_name = sample_base_name(name);
+    let mut pack = Vec::new();
+    for layer in 0..settings.velocity_layers {
+        let (low_velocity, high_velocity) = velocity_range(layer, settings.velocity_layers);
+        for round_robin in 1..=settings.round_robins {
+            // The seed picks the round robin's noise and humanization. Every layer reuses the
+            // same seeds, so a round robin sounds the same across layers apart from the velocity.
+            let render_settings = RenderSettings {
+                note: settings.note,
+                velocity: high_velocity as f32 / 127.0,
+                gate: settings.gate,
+                length: settings.length,
+                sample_rate: settings.sample_rate,
+                seed: round_robin as u64,
+            };
+            let mut samples = render::render_note(preset, &render_settings);
+            trim_silence(&mut samples, settings.sample_rate);
+
+            pack.push(PackSample {
+                file_name: format!("{base_name}_v{}_rr{round_robin}.wav", layer + 1),
+                low_velocity,
+                high_velocity,
+                round_robin,
+                samples,
+            });
+        }
+    }
+
+    if settings.normalize {
+        let peak = pack
+            .iter()
+            .flat_map(|sample| sample.samples.iter())
+            .fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+        if peak > 0.0 {
+            let gain = util::db_to_gain(NORMALIZE_PEAK_DB) / peak;
+            for sample in pack.iter_mut().flat_map(|sample| sample.samples.iter_mut()) {
+                *sample *= gain;
+            }
+        }
+    }
+
+    pack
+}
+
+fn sfz_mapping(pack: &[PackSample], settings: &ExportSettings) -> String {
+    // The release is part of the recording, so the samples play to the end on their own
+    let mut sfz = format!(
+        "<global> key={} amp_veltrack=0 loop_mode=one_shot\n",
+        settings.note
+    );
+    for group in pack.chunks(settings.round_robins as usize) {
+        let _ = write!(
+            sfz,
+            "\n<group> lovel={} hivel={}",
+            group[0].low_velocity, group[0].high_velocity
+        );
+        if settings.round_robins > 1 {
+            let _ = write!(sfz, " seq_length={}", settings.round_robins);
+        }
+        sfz.push('\n');
+
+        for sample in group {
+            let _ = write!(sfz, "<region> sample={}", sample.file_name);
+            if settings.round_robins > 1 {
+                let _ = write!(sfz, " seq_position={}", sample.round_robin);
+            }
+            sfz.push('\n');
+        }
+    }
+
+    sfz
+}
+
+fn decent_sampler_mapping(pack: &[PackSample], settings: &ExportSettings) -> String {
+    // Releasing the key shouldn't cut off the recorded tail
+    let release = pack
+        .iter()
+        .map(|sample| sample.samples.len() as f32 / settings.sample_rate)
+        .fold(0.0f32, f32::max);
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<DecentSampler minVersion=\"1.0.0\">\n  <groups>\n");
+    for group in pack.chunks(settings.round_robins as usize) {
+        let _ = write!(
+            xml,
+            "    <group loVel=\"{}\" hiVel=\"{}\" ampVelTrack=\"0\" attack=\"0\" release=\"{release:.3}\"",
+            group[0].low_velocity, group[0].high_velocity
+        );
+        if settings.round_robins > 1 {
+            let _ = write!(
+                xml,
+                " seqMode=\"round_robin\" seqLength=\"{}\"",
+                settings.round_robins
+            );
+        }
+        xml.push_str(">\n");
+
+        for sample in group {
+            let _ = write!(
+                xml,
+                "      <sample path=\"{}\" rootNote=\"{note}\" loNote=\"{note}\" hiNote=\"{note}\"",
+                sample.file_name,
+                note = settings.note
+            );
+            if settings.round_robins > 1 {
+                let _ = write!(xml, " seqPosition=\"{}\"", sample.round_robin);
+            }
+            xml.push_str("/>\n");
+        }
+        xml.push_str("    </group>\n");
+    }
+    xml.push_str("  </groups>\n</DecentSampler>\n");
+
+    xml
+}
+
+/// Render a preset, or the default patch without one, as a sample pack in `dir`. Writes a WAV file
+/// per velocity layer and round robin, plus `<name>.sfz` and `<name>.dspreset` mapping them all
+/// to the note. Returns the number of WAV files written.
+pub(crate) fn export_pack(
+    preset: Option<&Preset>,
+    name: &str,
+    settings: &ExportSettings,
+    dir: &Path,
+) -> io::Result<usize> {
+    let pack = render_pack(preset, name, settings);
+
+    fs::create_dir_all(dir)?;
+    for sample in &pack {
+        render::write_wav(
+            &dir.join(&sample.file_name),
+            &sample.samples,
+            settings.sample_rate as u32,
+            settings.format,
+        )
+        .map_err(io::Error::other)?;
+    }
+
+    let base_name = sample_base_name(name);
+    fs::write(
+        dir.join(format!("{base_name}.sfz")),
+        sfz_mapping(&pack, settings),
+    )?;
+    fs::write(
+        dir.join(format!("{base_name}.dspreset")),
+        decent_sampler_mapping(&pack, settings),
+    )?;
+
+    Ok(pack.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migrations::STATE_VERSION;
+
+    fn settings(round_robins: u32) -> ExportSettings {
+        ExportSettings {
+            note: 38,
+            velocity_layers: 1,
+            round_robins,
+            gate: 0.05,
+            length: 0.3,
+            sample_rate: 48000.0,
+            format: WavFormat::Float32,
+            normalize: false,
+        }
+    }
+
+    /// Fixed noise and no humanization, where round robins used to come out identical
+    fn fixed_noise_preset() -> Preset {
+        let json = format!(
+            r#"{{"name": "Fixed", "state_version": {STATE_VERSION}, "params": {{"noise_seed_mode": "Fixed Per Hit"}}}}"#
+        );
+        Preset::from_json(json.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn round_robins_differ() {
+        let preset = fixed_noise_preset();
+        let pack = render_pack(Some(&preset), "Fixed", &settings(2));
+        assert_eq!(pack.len(), 2);
+        assert_ne!(pack[0].samples, pack[1].samples);
+    }
+
+    #[test]
+    fn same_settings_render_the_same_pack() {
+        let preset = fixed_noise_preset();
+        let first = render_pack(Some(&preset), "Fixed", &settings(3));
+        let second = render_pack(Some(&preset), "Fixed", &settings(3));
+        for (first, second) in first.iter().zip(&second) {
+            assert_eq!(first.samples, second.samples, "{}", first.file_name);
+        }
+    }
+}
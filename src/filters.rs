@@ -0,0 +1,589 @@
+use nih_plug::prelude::Enum;
+use std::f32::consts::PI;
+
+/// Butterworth Q used for the cut filters and shelves
+const BUTTERWORTH_Q: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+/// Number of first order allpasses in the resonator's dispersion stage
+const DISPERSION_STAGES: usize = 4;
+
+/// Largest (negative) allpass coefficient, reached at full stiffness
+const MAX_DISPERSION_COEFF: f32 = 0.7;
+
+/// Most of the resonator's loop the dispersion stage may take up, the delay line needs the rest
+const MAX_DISPERSION_SHARE: f32 = 0.5;
+
+/// Pivot frequency of the tilt loop filter
+const TILT_PIVOT_HZ: f32 = 1000.0;
+
+pub(crate) struct OnePoleFilter {
+    a0: f32,
+    b1: f32,
+    z1: f32,
+}
+
+impl OnePoleFilter {
+    pub fn new() -> Self {
+        Self {
+            a0: 1.0,
+            b1: 0.0,
+            z1: 0.0,
+        }
+    }
+
+    /// Set filter coefficients for low/high pass
+    /// cutoff should be 0.0-1.0 (normalized frequency)
+    pub fn set_cutoff(&mut self, cutoff: f32, lowpass: bool) {
+        let g = (PI * cutoff).tan();
+        let a1 = if lowpass { (g - 1.0) / (g + 1.0) } else { (1.0 - g) / (1.0 + g) };
+        self.a0 = (1.0 + a1) / 2.0;
+        self.b1 = a1;
+    }
+
+    /// Lowpass for running at `factor` times the rate `cutoff` is normalized to, with the same
+    /// response in Hz. `set_cutoff()` is the bilinear transform of an analog one pole lowpass,
+    /// so this recovers that prototype from the pole and the gain at DC and transforms it again
+    /// at the higher rate.
+    pub fn set_lowpass_oversampled(&mut self, cutoff: f32, factor: usize) {
+        self.set_cutoff(cutoff, true);
+        if factor == 1 {
+            return;
+        }
+
+        let pole = -self.a0 * self.b1;
+        let gain = 2.0 * self.a0 / (1.0 - pole);
+        // The prototype's cutoff relative to the original rate. Past Nyquist the original filter
+        // is unstable, which can't be reproduced at a higher rate, so it stops there.
+        let prototype = ((1.0 - pole).atan2(1.0 + pole) / PI).min(0.5);
+        let g = (PI * prototype / factor as f32).tan();
+        let pole = (1.0 - g) / (1.0 + g);
+        self.a0 = gain * (1.0 - pole) / 2.0;
+        self.b1 = if self.a0 == 0.0 { 0.0 } else { -pole / self.a0 };
+    }
+
+    /// Process one sample through the filter
+    pub fn process(&mut self, input: f32) -> f32 {
+        let output = self.a0 * input + self.a0 * self.z1;
+        self.z1 = input - output * self.b1;
+        output
+    }
+
+    /// Reset filter state
+    pub fn reset(&mut self) {
+        self.z1 = 0.0;
+    }
+}
+
+/// Response of a `Biquad`
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum BiquadType {
+    Peak,
+    LowShelf,
+    HighShelf,
+    LowCut,
+    HighCut,
+}
+
+/// Generic RBJ cookbook biquad, generalised from the old single peak EQ
+pub(crate) struct Biquad {
+    /// `(kind, freq, gain, q, sample_rate)` the coefficients were calculated for
+    settings: Option<(BiquadType, f32, f32, f32, f32)>,
+    a0: f32,
+    a1: f32,
+    a2: f32,
+    b1: f32,
+    b2: f32,
+    x1: f32,
+    x2: f32,
+    y1: f32,
+    y2: f32,
+}
+
+impl Biquad {
+    pub fn new() -> Self {
+        Self {
+            settings: None,
+            a0: 1.0,
+            a1: 0.0,
+            a2: 0.0,
+            b1: 0.0,
+            b2: 0.0,
+            x1: 0.0,
+            x2: 0.0,
+            y1: 0.0,
+            y2: 0.0,
+        }
+    }
+
+    /// Configure the filter
+    /// freq: center/corner frequency in Hz
+    /// gain: gain in dB (ignored by the cut filters)
+    /// q: q factor (bandwidth)
+    /// Does nothing if the settings haven't changed since the last call
+    pub fn configure(&mut self, kind: BiquadType, freq: f32, gain: f32, q: f32, sample_rate: f32) {
+        let settings = Some((kind, freq, gain, q, sample_rate));
+        if settings == self.settings {
+            return;
+        }
+        self.settings = settings;
+
+        let freq = freq.min(sample_rate * 0.49);
+        let omega = 2.0 * PI * freq / sample_rate;
+        let (sin, cos) = omega.sin_cos();
+        let alpha = sin / (2.0 * q);
+        let a = 10.0_f32.powf(gain / 40.0);
+
+        let (b0, b1, b2, a0, a1, a2) = match kind {
+            BiquadType::Peak => (
+                1.0 + alpha * a,
+                -2.0 * cos,
+                1.0 - alpha * a,
+                1.0 + alpha / a,
+                -2.0 * cos,
+                1.0 - alpha / a,
+            ),
+            BiquadType::LowShelf => {
+                let sqrt_a_alpha = 2.0 * a.sqrt() * alpha;
+                (
+                    a * ((a + 1.0) - (a - 1.0) * cos + sqrt_a_alpha),
+                    2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                    a * ((a + 1.0) - (a - 1.0) * cos - sqrt_a_alpha),
+                    (a + 1.0) + (a - 1.0) * cos + sqrt_a_alpha,
+                    -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                    (a + 1.0) + (a - 1.0) * cos - sqrt_a_alpha,
+                )
+            }
+            BiquadType::HighShelf => {
+                let sqrt_a_alpha = 2.0 * a.sqrt() * alpha;
+                (
+                    a * ((a + 1.0) + (a - 1.0) * cos + sqrt_a_alpha),
+                    -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                    a * ((a + 1.0) + (a - 1.0) * cos - sqrt_a_alpha),
+                    (a + 1.0) - (a - 1.0) * cos + sqrt_a_alpha,
+                    2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                    (a + 1.0) - (a - 1.0) * cos - sqrt_a_alpha,
+                )
+            }
+            BiquadType::LowCut => (
+                (1.0 + cos) / 2.0,
+                -(1.0 + cos),
+                (1.0 + cos) / 2.0,
+                1.0 + alpha,
+                -2.0 * cos,
+                1.0 - alpha,
+            ),
+            BiquadType::HighCut => (
+                (1.0 - cos) / 2.0,
+                1.0 - cos,
+                (1.0 - cos) / 2.0,
+                1.0 + alpha,
+                -2.0 * cos,
+                1.0 - alpha,
+            ),
+        };
+
+        self.a0 = b0 / a0;
+        self.a1 = b1 / a0;
+        self.a2 = b2 / a0;
+        self.b1 = a1 / a0;
+        self.b2 = a2 / a0;
+    }
+
+    /// Process one sample through the filter
+    pub fn process(&mut self, input: f32) -> f32 {
+        let output = self.a0 * input + self.a1 * self.x1 + self.a2 * self.x2
+                   - self.b1 * self.y1 - self.b2 * self.y2;
+
+        self.x2 = self.x1;
+        self.x1 = input;
+        self.y2 = self.y1;
+        self.y1 = output;
+
+        output
+    }
+
+    /// Reset filter state
+    pub fn reset(&mut self) {
+        self.x1 = 0.0;
+        self.x2 = 0.0;
+        self.y1 = 0.0;
+        self.y2 = 0.0;
+    }
+}
+
+/// A single switchable band of a `LayerEQ`
+pub(crate) struct EqBand {
+    filter: Biquad,
+    kind: BiquadType,
+    enabled: bool,
+}
+
+impl EqBand {
+    fn new(kind: BiquadType) -> Self {
+        Self {
+            filter: Biquad::new(),
+            kind,
+            enabled: false,
+        }
+    }
+
+    /// Configure the band, disabled bands are skipped entirely
+    pub fn configure(&mut self, enabled: bool, freq: f32, gain: f32, q: f32, sample_rate: f32) {
+        // Don't let stale state from the last time the band was on leak into the signal
+        if enabled && !self.enabled {
+            self.filter.reset();
+        }
+        self.enabled = enabled;
+
+        if enabled {
+            self.filter.configure(self.kind, freq, gain, q, sample_rate);
+        }
+    }
+
+    /// Configure a cut filter or shelf, these use a fixed Butterworth slope
+    pub fn configure_fixed_q(&mut self, enabled: bool, freq: f32, gain: f32, sample_rate: f32) {
+        self.configure(enabled, freq, gain, BUTTERWORTH_Q, sample_rate);
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        if self.enabled {
+            self.filter.process(input)
+        } else {
+            input
+        }
+    }
+}
+
+/// Small parametric EQ used on every layer: low/high cut, low/high shelf and two peaks
+pub(crate) struct LayerEQ {
+    pub low_cut: EqBand,
+    pub low_shelf: EqBand,
+    pub peak: EqBand,
+    pub peak2: EqBand,
+    pub high_shelf: EqBand,
+    pub high_cut: EqBand,
+}
+
+impl LayerEQ {
+    pub fn new() -> Self {
+        Self {
+            low_cut: EqBand::new(BiquadType::LowCut),
+            low_shelf: EqBand::new(BiquadType::LowShelf),
+            peak: EqBand::new(BiquadType::Peak),
+            peak2: EqBand::new(BiquadType::Peak),
+            high_shelf: EqBand::new(BiquadType::HighShelf),
+            high_cut: EqBand::new(BiquadType::HighCut),
+        }
+    }
+
+    /// Process one sample through all enabled bands
+    pub fn process(&mut self, input: f32) -> f32 {
+        let output = self.low_cut.process(input);
+        let output = self.low_shelf.process(output);
+        let output = self.peak.process(output);
+        let output = self.peak2.process(output);
+        let output = self.high_shelf.process(output);
+        self.high_cut.process(output)
+    }
+
+    /// Reset filter state
+    pub fn reset(&mut self) {
+        self.low_cut.filter.reset();
+        self.low_shelf.filter.reset();
+        self.peak.filter.reset();
+        self.peak2.filter.reset();
+        self.high_shelf.filter.reset();
+        self.high_cut.filter.reset();
+    }
+}
+
+/// Filter used for damping inside the resonator's feedback loop
+#[derive(Enum, Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) enum LoopFilterType {
+    /// The original single pole damping filter
+    #[id = "one_pole"]
+    #[name = "1-Pole LP"]
+    OnePole,
+    #[id = "two_pole"]
+    #[name = "2-Pole LP"]
+    TwoPole,
+    /// Removes low end on every pass, the comb filter trick for thin, metallic rings
+    #[id = "high_pass"]
+    #[name = "High Pass"]
+    HighPass,
+    /// Trades lows against highs around a fixed pivot
+    #[id = "tilt"]
+    #[name = "Tilt"]
+    Tilt,
+}
+
+/// First order allpass, used in series to make higher partials travel faster (stiffness)
+struct AllpassStage {
+    x1: f32,
+    y1: f32,
+}
+
+impl AllpassStage {
+    fn process(&mut self, input: f32, coeff: f32) -> f32 {
+        let output = coeff * input + self.x1 - coeff * self.y1;
+        self.x1 = input;
+        self.y1 = output;
+        output
+    }
+}
+
+/// Damping and dispersion filters for the resonator's feedback loop
+pub(crate) struct LoopFilter {
+    filter_type: LoopFilterType,
+    /// `(damping, stiffness, sample_rate, oversampling)` the coefficients were calculated for
+    settings: Option<(f32, f32, f32, usize)>,
+    one_pole: OnePoleFilter,
+    biquad: Biquad,
+
+    // Tilt filter, a one pole TPT lowpass whose complement is the high band
+    tilt_g: f32,
+    tilt_state: f32,
+    tilt_low_gain: f32,
+    tilt_high_gain: f32,
+
+    dispersion: [AllpassStage; DISPERSION_STAGES],
+    /// Coefficient for the stiffness, before it's fitted to the loop
+    dispersion_coeff: f32,
+    /// `(loop_delay, inverted)` the loop's dispersion was fitted to
+    loop_settings: Option<(f32, bool)>,
+    loop_coeff: f32,
+    /// Phase delay of the dispersion stage at the loop's fundamental
+    loop_dispersion_delay: f32,
+}
+
+impl LoopFilter {
+    pub fn new() -> Self {
+        Self {
+            filter_type: LoopFilterType::OnePole,
+            settings: None,
+            one_pole: OnePoleFilter::new(),
+            biquad: Biquad::new(),
+            tilt_g: 0.0,
+            tilt_state: 0.0,
+            tilt_low_gain: 1.0,
+            tilt_high_gain: 1.0,
+            dispersion: std::array::from_fn(|_| AllpassStage { x1: 0.0, y1: 0.0 }),
+            dispersion_coeff: 0.0,
+            loop_settings: None,
+            loop_coeff: 0.0,
+            loop_dispersion_delay: 0.0,
+        }
+    }
+
+    /// Configure the loop filter
+    /// damping: 0.0-1.0 (bright to dark, for the high pass: thin to full)
+    /// stiffness: 0.0-1.0 (amount of allpass dispersion)
+    /// sample_rate: the host's rate, the filter runs `oversampling` times faster than that and
+    /// keeps the same response in Hz
+    /// Does nothing if the settings haven't changed since the last call
+    pub fn configure(
+        &mut self,
+        filter_type: LoopFilterType,
+        damping: f32,
+        stiffness: f32,
+        sample_rate: f32,
+        oversampling: usize,
+    ) {
+        let settings = Some((damping, stiffness, sample_rate, oversampling));
+        if filter_type == self.filter_type && settings == self.settings {
+            return;
+        }
+        self.settings = settings;
+
+        if filter_type != self.filter_type {
+            self.filter_type = filter_type;
+            self.reset();
+        }
+
+        // The cutoffs are relative to the host's rate so oversampling doesn't brighten the ring
+        let rate = sample_rate * oversampling as f32;
+        match filter_type {
+            LoopFilterType::OnePole => self.one_pole.set_lowpass_oversampled(1.0 - damping, oversampling),
+            LoopFilterType::TwoPole => {
+                let cutoff = (sample_rate * 0.45 * (1.0 - damping).powi(2)).max(50.0);
+                self.biquad.configure(BiquadType::HighCut, cutoff, 0.0, BUTTERWORTH_Q, rate);
+            }
+            LoopFilterType::HighPass => {
+                // 20 Hz at no damping up to 2 kHz
+                let cutoff = 20.0 * 100.0_f32.powf(damping);
+                self.biquad.configure(BiquadType::LowCut, cutoff, 0.0, BUTTERWORTH_Q, rate);
+            }
+            LoopFilterType::Tilt => {
+                let g = (PI * TILT_PIVOT_HZ / rate).tan();
+                self.tilt_g = g / (1.0 + g);
+
+                // Neither band may go above unity or the loop would blow up
+                let tilt = (damping - 0.5) * 2.0;
+                self.tilt_low_gain = (1.0 + tilt).min(1.0);
+                self.tilt_high_gain = (1.0 - tilt).min(1.0);
+            }
+        }
+
+        // Each stage should delay by the same time at any rate, so when oversampling the
+        // coefficient is moved to give `oversampling` times the delay in samples at DC
+        let coeff = -MAX_DISPERSION_COEFF * stiffness;
+        self.dispersion_coeff = if coeff == 0.0 || oversampling == 1 {
+            coeff
+        } else {
+            let delay = oversampling as f32 * (1.0 - coeff) / (1.0 + coeff);
+            (1.0 - delay) / (1.0 + delay)
+        };
+        self.loop_settings = None;
+    }
+
+    /// Fit the dispersion stage to a loop of `loop_delay` samples, `inverted` if the loop's
+    /// feedback is negative and it rings an octave lower. Short loops get less dispersion so
+    /// the delay line always has room left. Does nothing if the loop hasn't changed.
+    pub fn set_loop(&mut self, loop_delay: f32, inverted: bool) {
+        let settings = Some((loop_delay, inverted));
+        if settings == self.loop_settings {
+            return;
+        }
+        self.loop_settings = settings;
+
+        // The stage is bypassed entirely without stiffness
+        let coeff = self.dispersion_coeff;
+        if coeff == 0.0 {
+            self.loop_coeff = 0.0;
+            self.loop_dispersion_delay = 0.0;
+            return;
+        }
+
+        // A stage delays the most at DC for the negative coefficients stiffness uses, so
+        // limiting that limits the delay at the fundamental too
+        let max_stage_delay = MAX_DISPERSION_SHARE * loop_delay / DISPERSION_STAGES as f32;
+        let coeff = if (1.0 - coeff) / (1.0 + coeff) > max_stage_delay {
+            (1.0 - max_stage_delay) / (1.0 + max_stage_delay)
+        } else {
+            coeff
+        };
+
+        // Phase delay of every stage at the fundamental, where the loop has to be in tune
+        let omega = if inverted { PI } else { 2.0 * PI } / loop_delay;
+        let phase = 2.0 * (coeff * omega.sin() / (1.0 + coeff * omega.cos())).atan();
+        self.loop_coeff = coeff;
+        self.loop_dispersion_delay = DISPERSION_STAGES as f32 * (1.0 - phase / omega);
+    }
+
+    /// Delay the dispersion stage adds at the fundamental of the loop passed to `set_loop()`, in
+    /// samples. The resonator shortens its delay line by this amount so adding stiffness only
+    /// stretches the higher partials and doesn't detune the fundamental.
+    pub fn dispersion_delay(&self) -> f32 {
+        self.loop_dispersion_delay
+    }
+
+    /// Process one sample through the filter
+    pub fn process(&mut self, input: f32) -> f32 {
+        let output = match self.filter_type {
+            LoopFilterType::OnePole => self.one_pole.process(input),
+            LoopFilterType::TwoPole | LoopFilterType::HighPass => self.biquad.process(input),
+            LoopFilterType::Tilt => {
+                let v = (input - self.tilt_state) * self.tilt_g;
+                let low = v + self.tilt_state;
+                self.tilt_state = low + v;
+                low * self.tilt_low_gain + (input - low) * self.tilt_high_gain
+            }
+        };
+
+        if self.loop_coeff == 0.0 {
+            return output;
+        }
+
+        let coeff = self.loop_coeff;
+        self.dispersion
+            .iter_mut()
+            .fold(output, |sample, stage| stage.process(sample, coeff))
+    }
+
+    /// Reset filter state
+    pub fn reset(&mut self) {
+        self.one_pole.reset();
+        self.biquad.reset();
+        self.tilt_state = 0.0;
+        for stage in &mut self.dispersion {
+            stage.x1 = 0.0;
+            stage.y1 = 0.0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Frequency with the most energy between `low` and `high` Hz, in one cent steps
+    fn strongest_frequency(samples: &[f32], sample_rate: f32, low: f32, high: f32) -> f32 {
+        let window = |index: usize| 0.5 - 0.5 * (2.0 * PI * index as f32 / samples.len() as f32).cos();
+
+        let mut strongest = (0.0, low);
+        let mut freq = low;
+        while freq < high {
+            let omega = 2.0 * PI as f64 * freq as f64 / sample_rate as f64;
+            let (mut re, mut im) = (0.0f64, 0.0f64);
+            for (index, sample) in samples.iter().enumerate() {
+                let value = (sample * window(index)) as f64;
+                re += value * (omega * index as f64).cos();
+                im -= value * (omega * index as f64).sin();
+            }
+
+            let energy = re * re + im * im;
+            if energy > strongest.0 {
+                strongest = (energy, freq);
+            }
+            freq *= 2.0f32.powf(1.0 / 1200.0);
+        }
+
+        strongest.1
+    }
+
+    /// The resonator's loop like `DrumEngine::process_resonance()` runs it, excited by a click
+    fn ring(filter: &mut LoopFilter, loop_delay: f32, feedback: f32, length: usize) -> Vec<f32> {
+        const LINE_LENGTH: usize = 64;
+        let mut line = [0.0; LINE_LENGTH];
+        let mut write_pos = 0;
+
+        (0..length)
+            .map(|index| {
+                filter.set_loop(loop_delay, feedback < 0.0);
+                let delay = (loop_delay - filter.dispersion_delay()).max(1.0);
+                let read_pos = (write_pos + LINE_LENGTH) as f32 - delay;
+                let read_index = read_pos as usize;
+                let frac = read_pos - read_index as f32;
+                let older = line[read_index % LINE_LENGTH];
+                let newer = line[(read_index + 1) % LINE_LENGTH];
+
+                let click = (index == 0) as u8 as f32;
+                let input = click + filter.process(older + (newer - older) * frac) * feedback;
+                line[write_pos] = input;
+                write_pos = (write_pos + 1) % LINE_LENGTH;
+                input
+            })
+            .collect()
+    }
+
+    /// Full stiffness on the shortest tension, at 1x and at 4x oversampling. Negative
+    /// feedback like the resonator's makes the loop ring at half its rate.
+    #[test]
+    fn stiffness_keeps_fundamental_in_tune() {
+        let sample_rate = 48000.0;
+        for (loop_delay, oversampling) in [(5.0, 1), (20.0, 4)] {
+            let rate = sample_rate * oversampling as f32;
+            let expected = rate / (2.0 * loop_delay);
+            for stiffness in [0.0, 1.0] {
+                let mut filter = LoopFilter::new();
+                // A tilt without any tilt passes everything, which leaves just the dispersion
+                filter.configure(LoopFilterType::Tilt, 0.5, stiffness, sample_rate, oversampling);
+                let output = ring(&mut filter, loop_delay, -0.99, 8192);
+
+                let freq = strongest_frequency(&output, rate, expected * 0.8, expected * 1.2);
+                let cents = 1200.0 * (freq / expected).log2();
+                assert!(cents.abs() < 5.0, "{cents} cents off at {loop_delay} samples and stiffness {stiffness}");
+            }
+        }
+    }
+}
@@ -1,154 +1,223 @@
 use nih_plug::prelude::*;
 use nih_plug_vizia::ViziaState;
-use rand::Rng;
-use std::sync::Arc;
-use std::f32::consts::PI;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicU32;
+use std::sync::{Arc, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
 
+pub mod cli;
+mod dynamics;
+mod export;
+mod filters;
+mod migrations;
+mod modulation;
+mod morph;
+mod noise;
+mod oversampling;
+mod overrides;
+mod presets;
+mod randomizer;
+mod ratchet;
+mod render;
+mod sequencer;
 mod ui;
 
+use dynamics::{Compressor, Limiter, TransientShaper};
+use filters::{LayerEQ, LoopFilter, LoopFilterType};
+use modulation::{
+    LfoShape, ModDestination, ModMatrix, ModSource, NoteDivision, TransportInfo, MOD_SLOTS, MOD_SOURCES,
+};
+use morph::{Morph, MorphSnapshot};
+use noise::{NoiseColor, NoiseGenerator, NoiseSeedMode, Pcg32};
+use oversampling::{Decimator, OversamplingFactor, MAX_OVERSAMPLING};
+use overrides::ParamOverrides;
+use ratchet::{RatchetMode, MAX_RATCHET_HITS};
+use render::BounceTask;
+
+pub use presets::{Patch, PreparedPatch};
+use sequencer::{Pattern, Sequencer, StepTrigger};
+use ui::PresetDialog;
+
 
 /// The maximum size of a delay buffer for resonance.
 /// ~100ms at 44.1kHz sample rate
 const MAX_DELAY: usize = 4096;
 
-struct OnePoleFilter {
-    a0: f32,
-    b1: f32,
-    z1: f32,
-}
+/// Added to the noise seed for the snare layer so it doesn't use the exact same noise as the
+/// transient
+const SNARE_SEED_OFFSET: u64 = 0x9E37_79B9;
 
-impl OnePoleFilter {
-    fn new() -> Self {
-        Self {
-            a0: 1.0,
-            b1: 0.0,
-            z1: 0.0,
-        }
-    }
-    
-    /// Set filter coefficients for low/high pass
-    /// cutoff should be 0.0-1.0 (normalized frequency)
-    fn set_cutoff(&mut self, cutoff: f32, lowpass: bool) {
-        let g = (PI * cutoff).tan();
-        let a1 = if lowpass { (g - 1.0) / (g + 1.0) } else { (1.0 - g) / (1.0 + g) };
-        self.a0 = (1.0 + a1) / 2.0;
-        self.b1 = a1;
-    }
-    
-    /// Process one sample through the filter
-    fn process(&mut self, input: f32) -> f32 {
-        let output = self.a0 * input + self.a0 * self.z1;
-        self.z1 = input - output * self.b1;
-        output
-    }
-    
-    /// Reset filter state
-    fn reset(&mut self) {
-        self.z1 = 0.0;
-    }
-}
+/// Seed for the first round robin variation, the others follow it
+const ROUND_ROBIN_SEED: u64 = 0x5EED;
 
-/// Simple peak EQ
-struct PeakEQ {
-    a0: f32,
-    a1: f32,
-    a2: f32,
-    b1: f32,
-    b2: f32,
-    x1: f32,
-    x2: f32,
-    y1: f32,
-    y2: f32,
-}
+/// Spreads render seeds out so the fixed noise seeds of different renders don't overlap with
+/// each other's round robin slots
+const RENDER_SEED_STRIDE: u64 = 0x9E37_79B9_7F4A_7C15;
 
-impl PeakEQ {
-    fn new() -> Self {
-        Self {
-            a0: 1.0,
-            a1: 0.0,
-            a2: 0.0,
-            b1: 0.0,
-            b2: 0.0,
-            x1: 0.0,
-            x2: 0.0,
-            y1: 0.0,
-            y2: 0.0,
-        }
-    }
-    
-    /// Configure peak EQ
-    /// freq: 0.0-1.0 (normalized frequency)
-    /// gain: gain in dB
-    /// q: q factor (bandwidth)
-    fn configure(&mut self, freq: f32, gain: f32, q: f32, sample_rate: f32) {
-        let omega = 2.0 * PI * freq / sample_rate;
-        let alpha = (omega.sin()) / (2.0 * q);
-        let a = 10.0_f32.powf(gain / 40.0);
-        
-        let b0 = 1.0 + alpha * a;
-        let b1 = -2.0 * omega.cos();
-        let b2 = 1.0 - alpha * a;
-        let a0 = 1.0 + alpha / a;
-        let a1 = -2.0 * omega.cos();
-        let a2 = 1.0 - alpha / a;
-        
-        self.a0 = b0 / a0;
-        self.a1 = b1 / a0;
-        self.a2 = b2 / a0;
-        self.b1 = a1 / a0;
-        self.b2 = a2 / a0;
-    }
-    
-    /// Process one sample through the EQ
-    fn process(&mut self, input: f32) -> f32 {
-        let output = self.a0 * input + self.a1 * self.x1 + self.a2 * self.x2
-                   - self.b1 * self.y1 - self.b2 * self.y2;
-        
-        self.x2 = self.x1;
-        self.x1 = input;
-        self.y2 = self.y1;
-        self.y1 = output;
-        
-        output
-    }
-    
-    /// Reset filter state
-    fn reset(&mut self) {
-        self.x1 = 0.0;
-        self.x2 = 0.0;
-        self.y1 = 0.0;
-        self.y2 = 0.0;
-    }
-}
+/// Most hits that can wait for their start at the same time, further ones are dropped
+const MAX_PENDING_HITS: usize = 32;
 
-pub struct DrumSynth {
+/// While their parameters are smoothed, the filters' coefficients are recalculated once every
+/// this many samples instead of on every sample. Filters the mod matrix targets and morphs in
+/// progress still update on every sample.
+const FILTER_UPDATE_INTERVAL: u32 = 16;
+
+/// The resonator counts as silent once nothing in its delay line is louder than this (-100 dB)
+const SILENCE_LEVEL: f32 = 1e-5;
+
+/// Longest the resonator's tail is reported as, in case the feedback is close to infinite
+const MAX_TAIL_SECONDS: f32 = 30.0;
+
+/// Without a host the parameters are read once per block of this many samples
+const BLOCK_SIZE: usize = 64;
+
+/// The synthesis on its own, without a plugin host. The plugin wraps this, and it can be played
+/// directly through its public methods, for example from a game's audio engine or from tests.
+///
+/// Internally it reads the same nih_plug parameters and smoothers as the plugin, none of which
+/// show up in the public API. A patch overrides their values, which are looked up with a binary
+/// search over the patch's parameters. Only the levels, the tension, the sustain, the gain and
+/// the morph amount are read on every sample, the rest once per block or when the filters are
+/// updated, so the lookups cost little next to the synthesis.
+pub struct DrumEngine {
     params: Arc<DrumSynthParams>,
+    // Replaces the parameters' values when playing without a host
+    overrides: ParamOverrides,
     sample_rate: f32,
 
     // Transient layer (noise burst)
+    transient_noise: NoiseGenerator,
     transient_envelope: ADSREnvelope,
-    transient_eq: PeakEQ,
+    transient_eq: LayerEQ,
 
     // Resonance layer (Karplus-Strong)
     resonance_buffer: Vec<f32>,
     resonance_write_pos: usize,
-    resonance_read_pos: usize,
-    resonance_lowpass: OnePoleFilter, // Damping filter in feedback loop
-    resonance_eq: PeakEQ,
+    resonance_filter: LoopFilter, // Damping filter in feedback loop
+    resonance_eq: LayerEQ,
+    pitch_envelope: PitchEnvelope,
 
     // Snare noise (fed through resonator)
+    snare_noise: NoiseGenerator,
     noise_envelope: ADSREnvelope,
-    snare_eq: PeakEQ,
+    snare_eq: LayerEQ,
+    // The wires can start and stop a bit after the hit
+    snare_start: Countdown,
+    snare_stop: Countdown,
+
+    // Master dynamics
+    transient_shaper: TransientShaper,
+    compressor: Compressor,
+    limiter: Limiter,
+
+    // A/B snapshot morphing
+    morph: Morph,
+
+    // LFOs and mod envelopes
+    modulation: ModMatrix,
+
+    // Step sequencer
+    sequencer: Sequencer,
+    
+    // Releases sequencer steps after half a step, and notes that ended before all of their
+    // ratchet hits started after the last one
+    release_gate: Countdown,
+    ratchet_hold: u32,
+
+    // Picks new noise seeds when every hit should sound different
+    seed_rng: Pcg32,
+    // Set by `seed()` for offline renders, mixed into the fixed noise seed
+    render_seed: u64,
+
+    // Humanization of successive hits
+    humanize_rng: Pcg32,
+    round_robin_rng: Pcg32,
+    round_robin_pos: u32,
+    hit_variation: HitVariation,
+    pending_hits: [Option<PendingHit>; MAX_PENDING_HITS],
+    
+    /// Samples since the EQs and the loop filter were last configured
+    filter_steps: u32,
+    /// Configure the filters on the next sample, set when a new block or hit may have changed
+    /// their parameters
+    filters_stale: bool,
+    /// Output samples skipped while silent that the smoothers and mod sources haven't caught up
+    /// on yet
+    skipped_samples: u32,
+
+    // Oversampling of the per-sample synthesis
+    oversampling: OversamplingFactor,
+    process_mode: ProcessMode,
+    decimator: Decimator,
     
     // MIDI tracking
     midi_note_id: u8,
     midi_note_freq: f32,
+    // A held note is released by its NoteOff, sequencer steps leave it alone
+    midi_note_held: bool,
     is_playing: bool,
     
+    // Activity tracking so hosts can suspend the plugin, and synthesis is skipped while silent
+    /// Loudest sample in the resonator's delay line after the last block
+    resonance_peak: f32,
+    /// Longest delay read from the resonator since the last block, only that much of the delay
+    /// line can still come back out of it
+    resonance_span: usize,
+    /// Output samples since the last envelope ended and the resonator fell silent. Once this is
+    /// past the latency the output is silent too.
+    silent_samples: u32,
+    
+    /// Tempo for the current block's rolls
+    tempo: f64,
+}
+
+pub struct DrumSynth {
+    engine: DrumEngine,
+    
     // VIZIA editor state
     editor_state: Arc<ViziaState>,
 }
 
+/// Work the editor hands off to a background thread
+pub enum DrumSynthTask {
+    Bounce(BounceTask),
+    PresetDialog(PresetDialog),
+    /// Show a bounced file in the file manager
+    Reveal(PathBuf),
+}
+
+/// Per-hit deviations from the patch, rolled on every NoteOn from the humanize ranges
+#[derive(Clone, Copy)]
+struct HitVariation {
+    /// Level of both noise layers, from the hit's velocity
+    velocity: f32,
+    /// Delay length multiplier
+    tension: f32,
+    /// Added to the damping
+    damping: f32,
+    /// Transient level multiplier
+    level: f32,
+    /// EQ frequency multiplier for all layers
+    tone: f32,
+}
+
+impl HitVariation {
+    const NONE: Self = Self {
+        velocity: 1.0,
+        tension: 1.0,
+        damping: 0.0,
+        level: 1.0,
+        tone: 1.0,
+    };
+}
+
+/// A hit waiting for its micro-timing offset or a flam's delay to pass
+#[derive(Clone, Copy)]
+struct PendingHit {
+    samples_left: u32,
+    variation: HitVariation,
+}
+
 struct ADSREnvelope {
     state: ADSRState,
     attack_time: f32,
@@ -159,6 +228,15 @@ struct ADSREnvelope {
     current_level: f32,
     sample_rate: f32,
     hold_samples_left: usize,
+
+    // Curve exponents per segment, see `segment_shape()`
+    attack_curve: f32,
+    decay_curve: f32,
+    release_curve: f32,
+
+    /// Level the current segment started from and how far along it is, from 0.0 to 1.0
+    segment_start: f32,
+    segment_pos: f32,
 }
 
 #[derive(PartialEq)]
@@ -171,6 +249,26 @@ enum ADSRState {
     Release,
 }
 
+/// Natural log of 1000, a fully exponential segment covers 60 dB over its length
+const ENVELOPE_CURVE_RANGE: f32 = 6.907_755;
+
+/// Progress through a segment with a curve applied. `curve` is the exponent from
+/// `curve_exponent()`, positive values move fast at first and then slow down like a natural
+/// decay, negative values start slow and speed up.
+fn segment_shape(pos: f32, curve: f32) -> f32 {
+    if curve.abs() < 1e-3 {
+        pos
+    } else {
+        (1.0 - (-curve * pos).exp()) / (1.0 - (-curve).exp())
+    }
+}
+
+/// Convert a curve parameter in [-1.0, 1.0] (-1 logarithmic, 0 linear, 1 exponential) to the
+/// exponent used by `segment_shape()`
+fn curve_exponent(curve: f32) -> f32 {
+    curve * ENVELOPE_CURVE_RANGE
+}
+
 impl ADSREnvelope {
     fn new(sample_rate: f32) -> Self {
         Self {
@@ -183,6 +281,11 @@ impl ADSREnvelope {
             current_level: 0.0,
             sample_rate,
             hold_samples_left: 0,
+            attack_curve: 0.0,
+            decay_curve: 0.0,
+            release_curve: 0.0,
+            segment_start: 0.0,
+            segment_pos: 0.0,
         }
     }
 
@@ -194,16 +297,43 @@ impl ADSREnvelope {
         self.hold_time = hold;
     }
 
+    /// Curves for every segment in [-1.0, 1.0], -1 being logarithmic, 0 linear and 1
+    /// exponential. Segments are linear until this is called.
+    fn set_curves(&mut self, attack: f32, decay: f32, release: f32) {
+        self.attack_curve = curve_exponent(attack);
+        self.decay_curve = curve_exponent(decay);
+        self.release_curve = curve_exponent(release);
+    }
+
     fn note_on(&mut self) {
-        self.state = ADSRState::Attack;
         self.current_level = 0.0;
+        self.start_segment(ADSRState::Attack);
         self.hold_samples_left = (self.hold_time * self.sample_rate) as usize;
     }
 
     fn note_off(&mut self) {
         if self.state != ADSRState::Idle {
-            self.state = ADSRState::Release;
+            self.start_segment(ADSRState::Release);
+        }
+    }
+
+    fn start_segment(&mut self, state: ADSRState) {
+        self.state = state;
+        self.segment_start = self.current_level;
+        self.segment_pos = 0.0;
+    }
+
+    /// Move along the current segment towards `target` over `time` seconds. Returns whether the
+    /// segment has finished.
+    fn advance_segment(&mut self, target: f32, time: f32, curve: f32) -> bool {
+        self.segment_pos += 1.0 / (time * self.sample_rate).max(1.0);
+        if self.segment_pos >= 1.0 {
+            self.current_level = target;
+            return true;
         }
+
+        self.current_level = self.segment_start + (target - self.segment_start) * segment_shape(self.segment_pos, curve);
+        false
     }
 
     fn process(&mut self) -> f32 {
@@ -211,13 +341,11 @@ impl ADSREnvelope {
             ADSRState::Idle => 0.0,
             ADSRState::Attack => {
                 // Fast attack for percussive sounds
-                self.current_level += 1.0 / (self.attack_time * self.sample_rate).max(1.0);
-                if self.current_level >= 1.0 {
-                    self.current_level = 1.0;
+                if self.advance_segment(1.0, self.attack_time, self.attack_curve) {
                     if self.hold_time > 0.0 {
                         self.state = ADSRState::Hold;
                     } else {
-                        self.state = ADSRState::Decay;
+                        self.start_segment(ADSRState::Decay);
                     }
                 }
                 self.current_level
@@ -227,23 +355,19 @@ impl ADSREnvelope {
                     self.hold_samples_left -= 1;
                     1.0 // Hold at maximum level
                 } else {
-                    self.state = ADSRState::Decay;
+                    self.start_segment(ADSRState::Decay);
                     1.0
                 }
             }
             ADSRState::Decay => {
-                self.current_level -= (1.0 - self.sustain_level) / (self.decay_time * self.sample_rate).max(1.0);
-                if self.current_level <= self.sustain_level {
-                    self.current_level = self.sustain_level;
+                if self.advance_segment(self.sustain_level, self.decay_time, self.decay_curve) {
                     self.state = ADSRState::Sustain;
                 }
                 self.current_level
             }
             ADSRState::Sustain => self.sustain_level,
             ADSRState::Release => {
-                self.current_level -= self.current_level / (self.release_time * self.sample_rate).max(1.0);
-                if self.current_level <= 0.001 {
-                    self.current_level = 0.0;
+                if self.advance_segment(0.0, self.release_time, self.release_curve) {
                     self.state = ADSRState::Idle;
                 }
                 self.current_level
@@ -256,11 +380,92 @@ impl ADSREnvelope {
     }
 }
 
+/// Counts samples down to an event
+#[derive(Default)]
+struct Countdown {
+    samples_left: Option<u32>,
+}
+
+impl Countdown {
+    fn start(&mut self, samples: u32) {
+        self.samples_left = Some(samples);
+    }
+
+    fn cancel(&mut self) {
+        self.samples_left = None;
+    }
+
+    /// Advance by one sample, returns true once when the countdown reaches zero
+    fn tick(&mut self) -> bool {
+        match self.samples_left {
+            Some(0) => {
+                self.samples_left = None;
+                true
+            }
+            Some(samples_left) => {
+                self.samples_left = Some(samples_left - 1);
+                false
+            }
+            None => false,
+        }
+    }
+}
+
+/// Pitch offset of the resonator, starts at the full amount on every hit and falls back to zero
+struct PitchEnvelope {
+    amount: f32,
+    decay_time: f32,
+    /// Exponent applied to the remaining time, 1.0 is a linear drop
+    curve: f32,
+    sample_rate: f32,
+    /// 0.0 at the start of a hit, 1.0 once the sweep has finished
+    position: f32,
+}
+
+impl PitchEnvelope {
+    fn new(sample_rate: f32) -> Self {
+        Self {
+            amount: 0.0,
+            decay_time: 0.05,
+            curve: 1.0,
+            sample_rate,
+            position: 1.0,
+        }
+    }
+
+    /// `curve` goes from -1.0 to 1.0, 0.0 being linear
+    fn set_parameters(&mut self, amount: f32, decay_time: f32, curve: f32) {
+        self.amount = amount;
+        self.decay_time = decay_time;
+        self.curve = 2.0_f32.powf(curve * 3.0);
+    }
+
+    fn note_on(&mut self) {
+        self.position = 0.0;
+    }
+
+    /// The current offset in semitones
+    fn process(&mut self) -> f32 {
+        if self.position >= 1.0 {
+            return 0.0;
+        }
+
+        let offset = self.amount * (1.0 - self.position).powf(self.curve);
+        self.position += 1.0 / (self.decay_time * self.sample_rate).max(1.0);
+        offset
+    }
+}
+
 #[derive(Params)]
 pub struct DrumSynthParams {
     #[id = "gain"]
     pub gain: FloatParam,
 
+    /// How much quieter soft MIDI notes get, at 0 every note plays at full level. The
+    /// sequencer's step velocities always apply.
+    #[id = "vel_sens"]
+    pub velocity_sensitivity: FloatParam,
+
     // Impact layer params (transient)
     #[nested(group = "Impact")]
     impact_params: ImpactParams,
@@ -272,6 +477,53 @@ pub struct DrumSynthParams {
     // Snare layer params
     #[nested(group = "Snare")]
     snare_params: SnareParams,
+
+    // Master bus dynamics
+    #[nested(group = "Dynamics")]
+    dynamics_params: DynamicsParams,
+
+    // Per-hit variation
+    #[nested(group = "Humanize")]
+    humanize_params: HumanizeParams,
+
+    // Noise seeding shared by both noise layers
+    #[nested(group = "Noise")]
+    noise_params: NoiseParams,
+
+    // Oversampling settings
+    #[nested(group = "Quality")]
+    quality_params: QualityParams,
+
+    // A/B snapshot morphing
+    #[nested(group = "Morph")]
+    morph_params: MorphParams,
+
+    // Modulation sources and their routings
+    #[nested(array, group = "LFO")]
+    lfo_params: [LfoParams; MOD_SOURCES],
+
+    #[nested(array, group = "Mod Envelope")]
+    mod_envelope_params: [ModEnvelopeParams; MOD_SOURCES],
+
+    #[nested(array, group = "Mod Slot")]
+    mod_slot_params: [ModSlotParams; MOD_SLOTS],
+
+    // Step sequencer
+    #[nested(group = "Sequencer")]
+    sequencer_params: SequencerParams,
+
+    // Flams, drags and rolls from single notes
+    #[nested(group = "Ratchet")]
+    ratchet_params: RatchetParams,
+
+    /// Version of the saved state, used to upgrade sessions from older versions. See
+    /// `migrations.rs`.
+    #[persist = "state-version"]
+    state_version: Arc<AtomicU32>,
+
+    /// IDs of the parameters the randomizer leaves alone
+    #[persist = "random-locks"]
+    random_locks: Arc<RwLock<Vec<String>>>,
 }
 
 #[derive(Params)] 
@@ -288,17 +540,23 @@ struct ImpactParams {
     #[id = "tr_release"]
     pub release: FloatParam,
     
+    #[id = "tr_attack_curve"]
+    pub attack_curve: FloatParam,
+    
+    #[id = "tr_decay_curve"]
+    pub decay_curve: FloatParam,
+    
+    #[id = "tr_release_curve"]
+    pub release_curve: FloatParam,
+    
     #[id = "tr_level"]
     pub level: FloatParam,
     
-    #[id = "tr_eq_freq"]
-    pub eq_freq: FloatParam,
+    #[id = "tr_noise_color"]
+    pub noise_color: EnumParam<NoiseColor>,
     
-    #[id = "tr_eq_gain"]
-    pub eq_gain: FloatParam,
-    
-    #[id = "tr_eq_q"]
-    pub eq_q: FloatParam,
+    #[nested(id_prefix = "tr")]
+    pub eq: LayerEqParams,
 }
 
 #[derive(Params)]
@@ -312,17 +570,28 @@ struct TuningParams {
     #[id = "res_damping"]
     pub damping: FloatParam,
     
+    #[id = "res_loop_filter"]
+    pub loop_filter: EnumParam<LoopFilterType>,
+    
+    #[id = "res_stiffness"]
+    pub stiffness: FloatParam,
+    
     #[id = "res_level"]
     pub level: FloatParam,
     
-    #[id = "res_eq_freq"]
-    pub eq_freq: FloatParam,
+    /// Pitch offset at the start of every hit, falls back to the tuned pitch over the decay
+    #[id = "res_pitch_amount"]
+    pub pitch_amount: FloatParam,
+    
+    #[id = "res_pitch_decay"]
+    pub pitch_decay: FloatParam,
     
-    #[id = "res_eq_gain"]
-    pub eq_gain: FloatParam,
+    /// Negative values drop slowly at first, positive values drop fast and then level off
+    #[id = "res_pitch_curve"]
+    pub pitch_curve: FloatParam,
     
-    #[id = "res_eq_q"]
-    pub eq_q: FloatParam,
+    #[nested(id_prefix = "res")]
+    pub eq: LayerEqParams,
 }
 
 #[derive(Params)]
@@ -330,49 +599,385 @@ struct SnareParams {
     #[id = "snare_attack"]
     pub attack: FloatParam,
     
+    #[id = "snare_hold"]
+    pub hold: FloatParam,
+    
     #[id = "snare_decay"]
     pub decay: FloatParam,
     
+    #[id = "snare_sustain"]
+    pub sustain: FloatParam,
+    
+    #[id = "snare_release"]
+    pub release: FloatParam,
+    
+    #[id = "snare_attack_curve"]
+    pub attack_curve: FloatParam,
+    
+    #[id = "snare_decay_curve"]
+    pub decay_curve: FloatParam,
+    
+    #[id = "snare_release_curve"]
+    pub release_curve: FloatParam,
+    
+    /// Start of the wires relative to the hit
+    #[id = "snare_delay"]
+    pub delay: FloatParam,
+    
     #[id = "snare_level"] 
     pub level: FloatParam,
     
-    #[id = "snare_eq_freq"]
-    pub eq_freq: FloatParam,
+    #[id = "snare_noise_color"]
+    pub noise_color: EnumParam<NoiseColor>,
+    
+    #[nested(id_prefix = "snare")]
+    pub eq: LayerEqParams,
+}
+
+#[derive(Params)]
+struct HumanizeParams {
+    #[id = "hum_tension"]
+    pub tension: FloatParam,
+    
+    #[id = "hum_damping"]
+    pub damping: FloatParam,
+    
+    #[id = "hum_level"]
+    pub level: FloatParam,
+    
+    #[id = "hum_tone"]
+    pub tone: FloatParam,
     
-    #[id = "snare_eq_gain"]
-    pub eq_gain: FloatParam,
+    #[id = "hum_timing"]
+    pub timing: FloatParam,
     
-    #[id = "snare_eq_q"]
-    pub eq_q: FloatParam,
+    /// Cycle through this many fixed variations instead of rolling new ones, 1 is off
+    #[id = "hum_round_robin"]
+    pub round_robin: IntParam,
 }
 
-impl Default for DrumSynth {
+#[derive(Params)]
+struct NoiseParams {
+    #[id = "noise_seed_mode"]
+    pub seed_mode: EnumParam<NoiseSeedMode>,
+    
+    #[id = "noise_seed"]
+    pub seed: IntParam,
+}
+
+#[derive(Params)]
+struct QualityParams {
+    #[id = "oversampling"]
+    pub oversampling: EnumParam<OversamplingFactor>,
+    
+    /// Used instead of `oversampling` when the host renders offline
+    #[id = "oversampling_offline"]
+    pub offline_oversampling: EnumParam<OversamplingFactor>,
+}
+
+#[derive(Params)]
+struct MorphParams {
+    /// Position between snapshot A and B
+    #[id = "morph"]
+    pub amount: FloatParam,
+
+    #[persist = "morph-a"]
+    pub snapshot_a: Arc<RwLock<Option<MorphSnapshot>>>,
+
+    #[persist = "morph-b"]
+    pub snapshot_b: Arc<RwLock<Option<MorphSnapshot>>>,
+
+    /// Bumped whenever a snapshot changes so the audio thread knows to reload them
+    pub snapshot_generation: Arc<AtomicU32>,
+}
+
+#[derive(Params)]
+struct LfoParams {
+    #[id = "lfo_shape"]
+    pub shape: EnumParam<LfoShape>,
+
+    /// Free running rate, used when not synced to the host
+    #[id = "lfo_rate"]
+    pub rate: FloatParam,
+
+    #[id = "lfo_sync"]
+    pub sync: BoolParam,
+
+    #[id = "lfo_division"]
+    pub division: EnumParam<NoteDivision>,
+
+    /// Restart the cycle on every hit
+    #[id = "lfo_retrig"]
+    pub retrigger: BoolParam,
+}
+
+/// Free envelopes for the mod matrix, retriggered on every hit
+#[derive(Params)]
+struct ModEnvelopeParams {
+    #[id = "menv_attack"]
+    pub attack: FloatParam,
+
+    #[id = "menv_hold"]
+    pub hold: FloatParam,
+
+    #[id = "menv_decay"]
+    pub decay: FloatParam,
+}
+
+/// A single routing in the mod matrix
+#[derive(Params)]
+struct ModSlotParams {
+    #[id = "mod_src"]
+    pub source: EnumParam<ModSource>,
+
+    #[id = "mod_dst"]
+    pub destination: EnumParam<ModDestination>,
+
+    /// Fraction of the destination's range the source moves it by
+    #[id = "mod_depth"]
+    pub depth: FloatParam,
+}
+
+#[derive(Params)]
+struct SequencerParams {
+    #[id = "seq_on"]
+    pub enabled: BoolParam,
+
+    /// Number of steps in the loop
+    #[id = "seq_length"]
+    pub length: IntParam,
+
+    /// Length of a step
+    #[id = "seq_rate"]
+    pub rate: EnumParam<NoteDivision>,
+
+    /// Tempo of the sequencer's own clock, used when there's no host
+    #[id = "seq_tempo"]
+    pub tempo: FloatParam,
+
+    /// Level boost for accented steps
+    #[id = "seq_accent"]
+    pub accent: FloatParam,
+
+    /// Time between a flam's grace note and the hit
+    #[id = "seq_flam"]
+    pub flam_time: FloatParam,
+
+    #[persist = "seq-pattern"]
+    pub pattern: Arc<RwLock<Pattern>>,
+
+    /// Bumped whenever the pattern changes so the audio thread knows to reload it
+    pub pattern_generation: Arc<AtomicU32>,
+}
+
+#[derive(Params)]
+struct RatchetParams {
+    #[id = "rat_mode"]
+    pub mode: EnumParam<RatchetMode>,
+
+    /// Time between a flam's or drag's grace note and the hit
+    #[id = "rat_grace_time"]
+    pub grace_time: FloatParam,
+
+    /// Grace note level relative to the hit, the sequencer's flams use it too
+    #[id = "rat_grace_level"]
+    pub grace_level: FloatParam,
+
+    #[id = "rat_roll_rate"]
+    pub roll_rate: EnumParam<NoteDivision>,
+
+    /// Number of hits in a roll
+    #[id = "rat_roll_count"]
+    pub roll_count: IntParam,
+
+    /// Positive values make a roll build up, negative values make it fade out
+    #[id = "rat_roll_ramp"]
+    pub roll_ramp: FloatParam,
+}
+
+/// Per-layer parametric EQ, nested with the layer's ID prefix so the first peak keeps the
+/// original `<layer>_eq_freq`, `<layer>_eq_gain` and `<layer>_eq_q` IDs
+#[derive(Params)]
+struct LayerEqParams {
+    #[id = "eq_lc_on"]
+    pub low_cut_enabled: BoolParam,
+    
+    #[id = "eq_lc_freq"]
+    pub low_cut_freq: FloatParam,
+    
+    #[id = "eq_ls_on"]
+    pub low_shelf_enabled: BoolParam,
+    
+    #[id = "eq_ls_freq"]
+    pub low_shelf_freq: FloatParam,
+    
+    #[id = "eq_ls_gain"]
+    pub low_shelf_gain: FloatParam,
+    
+    #[id = "eq_on"]
+    pub peak_enabled: BoolParam,
+    
+    #[id = "eq_freq"]
+    pub peak_freq: FloatParam,
+    
+    #[id = "eq_gain"]
+    pub peak_gain: FloatParam,
+    
+    #[id = "eq_q"]
+    pub peak_q: FloatParam,
+    
+    #[id = "eq2_on"]
+    pub peak2_enabled: BoolParam,
+    
+    #[id = "eq2_freq"]
+    pub peak2_freq: FloatParam,
+    
+    #[id = "eq2_gain"]
+    pub peak2_gain: FloatParam,
+    
+    #[id = "eq2_q"]
+    pub peak2_q: FloatParam,
+    
+    #[id = "eq_hs_on"]
+    pub high_shelf_enabled: BoolParam,
+    
+    #[id = "eq_hs_freq"]
+    pub high_shelf_freq: FloatParam,
+    
+    #[id = "eq_hs_gain"]
+    pub high_shelf_gain: FloatParam,
+    
+    #[id = "eq_hc_on"]
+    pub high_cut_enabled: BoolParam,
+    
+    #[id = "eq_hc_freq"]
+    pub high_cut_freq: FloatParam,
+}
+
+#[derive(Params)]
+struct DynamicsParams {
+    #[id = "dyn_shaper_attack"]
+    pub shaper_attack: FloatParam,
+    
+    #[id = "dyn_shaper_sustain"]
+    pub shaper_sustain: FloatParam,
+    
+    #[id = "dyn_comp_threshold"]
+    pub comp_threshold: FloatParam,
+    
+    #[id = "dyn_comp_ratio"]
+    pub comp_ratio: FloatParam,
+    
+    #[id = "dyn_comp_attack"]
+    pub comp_attack: FloatParam,
+    
+    #[id = "dyn_comp_release"]
+    pub comp_release: FloatParam,
+    
+    #[id = "dyn_comp_makeup"]
+    pub comp_makeup: FloatParam,
+    
+    #[id = "dyn_comp_mix"]
+    pub comp_mix: FloatParam,
+    
+    #[id = "dyn_limiter"]
+    pub limiter_enabled: BoolParam,
+    
+    #[id = "dyn_limiter_ceiling"]
+    pub limiter_ceiling: FloatParam,
+}
+
+impl Default for DrumEngine {
     fn default() -> Self {
+        let params = Arc::new(DrumSynthParams::default());
+
         Self {
-            params: Arc::new(DrumSynthParams::default()),
+            morph: Morph::new(&params),
+            modulation: ModMatrix::new(&params),
+            sequencer: Sequencer::new(),
+            release_gate: Countdown::default(),
+            ratchet_hold: 0,
+            overrides: ParamOverrides::new(&params),
+            params,
             sample_rate: 44100.0,
 
+            transient_noise: NoiseGenerator::new(0),
             transient_envelope: ADSREnvelope::new(44100.0),
-            transient_eq: PeakEQ::new(),
+            transient_eq: LayerEQ::new(),
 
             resonance_buffer: vec![0.0; MAX_DELAY],
             resonance_write_pos: 0,
-            resonance_read_pos: 0,
-            resonance_lowpass: OnePoleFilter::new(),
-            resonance_eq: PeakEQ::new(),
+            resonance_filter: LoopFilter::new(),
+            resonance_eq: LayerEQ::new(),
+            pitch_envelope: PitchEnvelope::new(44100.0),
 
+            snare_noise: NoiseGenerator::new(SNARE_SEED_OFFSET),
             noise_envelope: ADSREnvelope::new(44100.0),
-            snare_eq: PeakEQ::new(),
+            snare_start: Countdown::default(),
+            snare_stop: Countdown::default(),
+            snare_eq: LayerEQ::new(),
+
+            transient_shaper: TransientShaper::new(),
+            compressor: Compressor::new(),
+            limiter: Limiter::new(),
+
+            seed_rng: Pcg32::new(
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|time| time.as_nanos() as u64)
+                    .unwrap_or_default(),
+            ),
+            render_seed: 0,
+
+            humanize_rng: Pcg32::new(ROUND_ROBIN_SEED),
+            round_robin_rng: Pcg32::new(ROUND_ROBIN_SEED),
+            round_robin_pos: 0,
+            hit_variation: HitVariation::NONE,
+            pending_hits: [None; MAX_PENDING_HITS],
+            filter_steps: 0,
+            filters_stale: true,
+            skipped_samples: 0,
+
+            oversampling: OversamplingFactor::X1,
+            process_mode: ProcessMode::Realtime,
+            decimator: Decimator::new(),
             
             midi_note_id: 0,
             midi_note_freq: 1.0,
+            midi_note_held: false,
             is_playing: false,
             
-            editor_state: ViziaState::new(|| (1000, 750)),
+            resonance_peak: 0.0,
+            resonance_span: 0,
+            silent_samples: 0,
+            
+            tempo: 120.0,
+        }
+    }
+}
+
+impl Default for DrumSynth {
+    fn default() -> Self {
+        Self {
+            engine: DrumEngine::default(),
+            editor_state: ViziaState::new(|| (1200, 1250)),
         }
     }
 }
 
+/// Curve of an envelope segment, -1 is logarithmic, 0 linear and 1 exponential
+fn envelope_curve_param(name: &str, default: f32) -> FloatParam {
+    FloatParam::new(
+        name,
+        default,
+        FloatRange::Linear {
+            min: -1.0,
+            max: 1.0,
+        },
+    )
+    .with_step_size(0.01)
+}
+
 impl Default for ImpactParams {
     fn default() -> Self {
         Self {
@@ -381,7 +986,7 @@ impl Default for ImpactParams {
                 0.0005, // 0.5ms
                 FloatRange::Skewed {
                     min: 0.0001,
-                    max: 0.01,
+                    max: 0.1,
                     factor: FloatRange::skew_factor(-1.0)
                 },
             )
@@ -392,7 +997,7 @@ impl Default for ImpactParams {
                 0.0, // 0ms
                 FloatRange::Skewed {
                     min: 0.0,
-                    max: 0.01,
+                    max: 0.1,
                     factor: FloatRange::skew_factor(-1.0)
                 },
             )
@@ -402,24 +1007,28 @@ impl Default for ImpactParams {
                 "Decay",
                 0.02, // 20ms
                 FloatRange::Skewed {
-                    min: 0.01,
-                    max: 0.03,
-                    factor: FloatRange::skew_factor(-1.0)
+                    min: 0.005,
+                    max: 2.0,
+                    factor: FloatRange::skew_factor(-2.0)
                 },
             )
             .with_unit(" s"),
             
             release: FloatParam::new(
                 "Release",
-                0.015, // 15ms
+                0.1, // 100ms
                 FloatRange::Skewed {
-                    min: 0.01,
-                    max: 0.03,
-                    factor: FloatRange::skew_factor(-1.0)
+                    min: 0.005,
+                    max: 2.0,
+                    factor: FloatRange::skew_factor(-2.0)
                 },
             )
             .with_unit(" s"),
             
+            attack_curve: envelope_curve_param("Attack Curve", 0.0),
+            decay_curve: envelope_curve_param("Decay Curve", 1.0),
+            release_curve: envelope_curve_param("Release Curve", 1.0),
+            
             level: FloatParam::new(
                 "Level",
                 0.8,
@@ -429,36 +1038,9 @@ impl Default for ImpactParams {
                 },
             ),
             
-            eq_freq: FloatParam::new(
-                "Tone",
-                500.0,
-                FloatRange::Skewed {
-                    min: 100.0,
-                    max: 5000.0,
-                    factor: FloatRange::skew_factor(-1.0)
-                },
-            )
-            .with_unit(" Hz"),
+            noise_color: EnumParam::new("Noise Color", NoiseColor::White),
             
-            eq_gain: FloatParam::new(
-                "Tone Gain",
-                3.0,
-                FloatRange::Linear {
-                    min: -12.0,
-                    max: 12.0,
-                },
-            )
-            .with_unit(" dB"),
-            
-            eq_q: FloatParam::new(
-                "Tone Width",
-                1.0,
-                FloatRange::Skewed {
-                    min: 0.1,
-                    max: 10.0,
-                    factor: FloatRange::skew_factor(-1.0)
-                },
-            ),
+            eq: LayerEqParams::new(500.0, 3.0, 100.0, 5000.0),
         }
     }
 }
@@ -495,6 +1077,17 @@ impl Default for TuningParams {
                 },
             ),
             
+            loop_filter: EnumParam::new("Loop Filter", LoopFilterType::OnePole),
+            
+            stiffness: FloatParam::new(
+                "Stiffness",
+                0.0, // Harmonic partials
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: 1.0, // Inharmonic, bell-like
+                },
+            ),
+            
             level: FloatParam::new(
                 "Level",
                 0.8,
@@ -504,36 +1097,38 @@ impl Default for TuningParams {
                 },
             ),
             
-            eq_freq: FloatParam::new(
-                "Tone",
-                800.0,
-                FloatRange::Skewed {
-                    min: 100.0,
-                    max: 5000.0,
-                    factor: FloatRange::skew_factor(-1.0)
-                },
-            )
-            .with_unit(" Hz"),
-            
-            eq_gain: FloatParam::new(
-                "Tone Gain",
+            pitch_amount: FloatParam::new(
+                "Pitch Env",
                 0.0,
                 FloatRange::Linear {
-                    min: -12.0,
-                    max: 12.0,
+                    min: 0.0,
+                    max: 48.0,
                 },
             )
-            .with_unit(" dB"),
+            .with_step_size(0.1)
+            .with_unit(" st"),
             
-            eq_q: FloatParam::new(
-                "Tone Width",
-                1.0,
+            pitch_decay: FloatParam::new(
+                "Pitch Decay",
+                0.05, // 50ms
                 FloatRange::Skewed {
-                    min: 0.1,
-                    max: 10.0,
-                    factor: FloatRange::skew_factor(-1.0)
+                    min: 0.001,
+                    max: 1.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_unit(" s"),
+            
+            pitch_curve: FloatParam::new(
+                "Pitch Curve",
+                0.5,
+                FloatRange::Linear {
+                    min: -1.0,
+                    max: 1.0,
                 },
             ),
+            
+            eq: LayerEqParams::new(800.0, 0.0, 100.0, 5000.0),
         }
     }
 }
@@ -546,7 +1141,18 @@ impl Default for SnareParams {
                 0.001,
                 FloatRange::Skewed {
                     min: 0.0001,
-                    max: 0.01,
+                    max: 0.1,
+                    factor: FloatRange::skew_factor(-1.0)
+                },
+            )
+            .with_unit(" s"),
+            
+            hold: FloatParam::new(
+                "Hold",
+                0.0,
+                FloatRange::Skewed {
+                    min: 0.0,
+                    max: 0.1,
                     factor: FloatRange::skew_factor(-1.0)
                 },
             )
@@ -557,7 +1163,42 @@ impl Default for SnareParams {
                 0.1,
                 FloatRange::Skewed {
                     min: 0.01,
-                    max: 0.5,
+                    max: 2.0,
+                    factor: FloatRange::skew_factor(-2.0)
+                },
+            )
+            .with_unit(" s"),
+            
+            sustain: FloatParam::new(
+                "Sustain",
+                0.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: 1.0,
+                },
+            ),
+            
+            release: FloatParam::new(
+                "Release",
+                0.35,
+                FloatRange::Skewed {
+                    min: 0.005,
+                    max: 8.0,
+                    factor: FloatRange::skew_factor(-2.0)
+                },
+            )
+            .with_unit(" s"),
+            
+            attack_curve: envelope_curve_param("Attack Curve", 0.0),
+            decay_curve: envelope_curve_param("Decay Curve", 1.0),
+            release_curve: envelope_curve_param("Release Curve", 1.0),
+            
+            delay: FloatParam::new(
+                "Delay",
+                0.0,
+                FloatRange::Skewed {
+                    min: 0.0,
+                    max: 0.05,
                     factor: FloatRange::skew_factor(-1.0)
                 },
             )
@@ -572,132 +1213,1233 @@ impl Default for SnareParams {
                 },
             ),
             
-            eq_freq: FloatParam::new(
+            noise_color: EnumParam::new("Noise Color", NoiseColor::White),
+            
+            eq: LayerEqParams::new(2000.0, 6.0, 500.0, 10000.0),
+        }
+    }
+}
+
+impl LayerEqParams {
+    /// The first peak is the layer's main tone control, everything else starts out disabled
+    fn new(tone_freq: f32, tone_gain: f32, min_freq: f32, max_freq: f32) -> Self {
+        Self {
+            low_cut_enabled: BoolParam::new("Low Cut", false),
+            
+            low_cut_freq: FloatParam::new(
+                "Low Cut Freq",
+                60.0,
+                FloatRange::Skewed {
+                    min: 20.0,
+                    max: 2000.0,
+                    factor: FloatRange::skew_factor(-1.0)
+                },
+            )
+            .with_unit(" Hz"),
+            
+            low_shelf_enabled: BoolParam::new("Low Shelf", false),
+            
+            low_shelf_freq: FloatParam::new(
+                "Low Shelf Freq",
+                200.0, // Drum body
+                FloatRange::Skewed {
+                    min: 20.0,
+                    max: 2000.0,
+                    factor: FloatRange::skew_factor(-1.0)
+                },
+            )
+            .with_unit(" Hz"),
+            
+            low_shelf_gain: FloatParam::new(
+                "Low Shelf Gain",
+                0.0,
+                FloatRange::Linear {
+                    min: -18.0,
+                    max: 18.0,
+                },
+            )
+            .with_unit(" dB"),
+            
+            peak_enabled: BoolParam::new("Peak", true),
+            
+            peak_freq: FloatParam::new(
                 "Tone",
-                2000.0, // 2kHz
+                tone_freq,
                 FloatRange::Skewed {
-                    min: 500.0,
-                    max: 10000.0,
+                    min: min_freq,
+                    max: max_freq,
                     factor: FloatRange::skew_factor(-1.0)
                 },
             )
             .with_unit(" Hz"),
             
-            eq_gain: FloatParam::new(
+            peak_gain: FloatParam::new(
                 "Tone Gain",
-                6.0,
+                tone_gain,
                 FloatRange::Linear {
-                    min: 0.0,
-                    max: 12.0,
+                    min: -18.0,
+                    max: 18.0,
                 },
             )
             .with_unit(" dB"),
             
-            eq_q: FloatParam::new(
+            peak_q: FloatParam::new(
                 "Tone Width",
                 1.0,
                 FloatRange::Skewed {
                     min: 0.1,
-                    max: 5.0,
+                    max: 10.0,
+                    factor: FloatRange::skew_factor(-1.0)
+                },
+            ),
+            
+            peak2_enabled: BoolParam::new("Peak 2", false),
+            
+            peak2_freq: FloatParam::new(
+                "Tone 2",
+                2000.0, // Crack
+                FloatRange::Skewed {
+                    min: 20.0,
+                    max: 20000.0,
+                    factor: FloatRange::skew_factor(-1.0)
+                },
+            )
+            .with_unit(" Hz"),
+            
+            peak2_gain: FloatParam::new(
+                "Tone 2 Gain",
+                0.0,
+                FloatRange::Linear {
+                    min: -18.0,
+                    max: 18.0,
+                },
+            )
+            .with_unit(" dB"),
+            
+            peak2_q: FloatParam::new(
+                "Tone 2 Width",
+                1.0,
+                FloatRange::Skewed {
+                    min: 0.1,
+                    max: 10.0,
                     factor: FloatRange::skew_factor(-1.0)
                 },
             ),
+            
+            high_shelf_enabled: BoolParam::new("High Shelf", false),
+            
+            high_shelf_freq: FloatParam::new(
+                "High Shelf Freq",
+                6000.0,
+                FloatRange::Skewed {
+                    min: 1000.0,
+                    max: 20000.0,
+                    factor: FloatRange::skew_factor(-1.0)
+                },
+            )
+            .with_unit(" Hz"),
+            
+            high_shelf_gain: FloatParam::new(
+                "High Shelf Gain",
+                0.0,
+                FloatRange::Linear {
+                    min: -18.0,
+                    max: 18.0,
+                },
+            )
+            .with_unit(" dB"),
+            
+            high_cut_enabled: BoolParam::new("High Cut", false),
+            
+            high_cut_freq: FloatParam::new(
+                "High Cut Freq",
+                12000.0,
+                FloatRange::Skewed {
+                    min: 1000.0,
+                    max: 20000.0,
+                    factor: FloatRange::skew_factor(-1.0)
+                },
+            )
+            .with_unit(" Hz"),
         }
     }
 }
 
-impl Default for DrumSynthParams {
+impl Default for DynamicsParams {
     fn default() -> Self {
         Self {
-            gain: FloatParam::new(
-                "Gain",
-                -6.0,
+            shaper_attack: FloatParam::new(
+                "Transient Attack",
+                0.0,
                 FloatRange::Linear {
-                    min: -30.0,
-                    max: 6.0,
+                    min: -100.0,
+                    max: 100.0,
+                },
+            )
+            .with_unit(" %"),
+            
+            shaper_sustain: FloatParam::new(
+                "Transient Sustain",
+                0.0,
+                FloatRange::Linear {
+                    min: -100.0,
+                    max: 100.0,
+                },
+            )
+            .with_unit(" %"),
+            
+            comp_threshold: FloatParam::new(
+                "Comp Threshold",
+                -12.0,
+                FloatRange::Linear {
+                    min: -40.0,
+                    max: 0.0,
                 },
             )
-            .with_smoother(SmoothingStyle::Logarithmic(50.0))
-            .with_step_size(0.01)
             .with_unit(" dB"),
             
-            impact_params: ImpactParams::default(),
-            tuning_params: TuningParams::default(),
-            snare_params: SnareParams::default(),
+            comp_ratio: FloatParam::new(
+                "Comp Ratio",
+                1.0, // Off
+                FloatRange::Skewed {
+                    min: 1.0,
+                    max: 20.0,
+                    factor: FloatRange::skew_factor(-1.0)
+                },
+            ),
+            
+            comp_attack: FloatParam::new(
+                "Comp Attack",
+                0.01, // 10ms lets the stick through
+                FloatRange::Skewed {
+                    min: 0.0001,
+                    max: 0.1,
+                    factor: FloatRange::skew_factor(-1.0)
+                },
+            )
+            .with_unit(" s"),
+            
+            comp_release: FloatParam::new(
+                "Comp Release",
+                0.1,
+                FloatRange::Skewed {
+                    min: 0.01,
+                    max: 1.0,
+                    factor: FloatRange::skew_factor(-1.0)
+                },
+            )
+            .with_unit(" s"),
+            
+            comp_makeup: FloatParam::new(
+                "Comp Makeup",
+                0.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: 24.0,
+                },
+            )
+            .with_unit(" dB"),
+            
+            comp_mix: FloatParam::new(
+                "Comp Mix",
+                1.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: 1.0,
+                },
+            ),
+            
+            limiter_enabled: BoolParam::new("Limiter", true),
+            
+            limiter_ceiling: FloatParam::new(
+                "Limiter Ceiling",
+                -0.3,
+                FloatRange::Linear {
+                    min: -12.0,
+                    max: 0.0,
+                },
+            )
+            .with_unit(" dB"),
         }
     }
 }
 
-impl DrumSynth {
-    fn calculate_noise() -> f32 {
-        let mut rng = rand::thread_rng();
-        rng.gen_range(-1.0..1.0)
+impl Default for HumanizeParams {
+    fn default() -> Self {
+        Self {
+            tension: FloatParam::new(
+                "Tension Variation",
+                0.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: 10.0,
+                },
+            )
+            .with_unit(" %"),
+            
+            damping: FloatParam::new(
+                "Damping Variation",
+                0.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: 0.2,
+                },
+            ),
+            
+            level: FloatParam::new(
+                "Level Variation",
+                0.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: 6.0,
+                },
+            )
+            .with_unit(" dB"),
+            
+            tone: FloatParam::new(
+                "Tone Variation",
+                0.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: 12.0,
+                },
+            )
+            .with_unit(" st"),
+            
+            timing: FloatParam::new(
+                "Timing Variation",
+                0.0,
+                FloatRange::Skewed {
+                    min: 0.0,
+                    max: 0.02, // 20ms is already sloppy
+                    factor: FloatRange::skew_factor(-1.0)
+                },
+            )
+            .with_unit(" s"),
+            
+            round_robin: IntParam::new(
+                "Round Robin",
+                1,
+                IntRange::Linear {
+                    min: 1,
+                    max: 8,
+                },
+            ),
+        }
     }
-    
-    fn process_transient(&mut self) -> f32 {
-        // Use white noise burst for transient (not square wave)
-        let noise = Self::calculate_noise();
-        
-        // Apply envelope to transient
-        let envelope = self.transient_envelope.process();
-        
-        // Configure transient EQ
-        self.transient_eq.configure(
-            self.params.impact_params.eq_freq.smoothed.next(), 
-            self.params.impact_params.eq_gain.smoothed.next(),
-            self.params.impact_params.eq_q.smoothed.next(),
-            self.sample_rate
+}
+
+impl Default for NoiseParams {
+    fn default() -> Self {
+        Self {
+            seed_mode: EnumParam::new("Noise Seed Mode", NoiseSeedMode::RandomPerHit),
+            
+            seed: IntParam::new(
+                "Noise Seed",
+                1,
+                IntRange::Linear {
+                    min: 0,
+                    max: 9999,
+                },
+            ),
+        }
+    }
+}
+
+impl Default for QualityParams {
+    fn default() -> Self {
+        Self {
+            oversampling: EnumParam::new("Oversampling", OversamplingFactor::X1),
+            offline_oversampling: EnumParam::new("Offline Oversampling", OversamplingFactor::X4),
+        }
+    }
+}
+
+impl Default for MorphParams {
+    fn default() -> Self {
+        Self {
+            amount: FloatParam::new(
+                "Morph",
+                0.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: 100.0,
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(20.0))
+            .with_unit(" %"),
+
+            snapshot_a: Arc::new(RwLock::new(None)),
+            snapshot_b: Arc::new(RwLock::new(None)),
+            snapshot_generation: Arc::new(AtomicU32::new(0)),
+        }
+    }
+}
+
+impl Default for LfoParams {
+    fn default() -> Self {
+        Self {
+            shape: EnumParam::new("LFO Shape", LfoShape::Sine),
+
+            rate: FloatParam::new(
+                "LFO Rate",
+                2.0,
+                FloatRange::Skewed {
+                    min: 0.05,
+                    max: 30.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_unit(" Hz"),
+
+            sync: BoolParam::new("LFO Sync", false),
+            division: EnumParam::new("LFO Division", NoteDivision::Quarter),
+            retrigger: BoolParam::new("LFO Retrigger", false),
+        }
+    }
+}
+
+impl Default for ModEnvelopeParams {
+    fn default() -> Self {
+        Self {
+            attack: FloatParam::new(
+                "Mod Env Attack",
+                0.001,
+                FloatRange::Skewed {
+                    min: 0.0001,
+                    max: 1.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_unit(" s"),
+
+            hold: FloatParam::new(
+                "Mod Env Hold",
+                0.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: 0.5,
+                },
+            )
+            .with_unit(" s"),
+
+            decay: FloatParam::new(
+                "Mod Env Decay",
+                0.1,
+                FloatRange::Skewed {
+                    min: 0.001,
+                    max: 2.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_unit(" s"),
+        }
+    }
+}
+
+impl Default for ModSlotParams {
+    fn default() -> Self {
+        Self {
+            source: EnumParam::new("Mod Source", ModSource::None),
+            destination: EnumParam::new("Mod Destination", ModDestination::None),
+
+            depth: FloatParam::new(
+                "Mod Depth",
+                0.0,
+                FloatRange::Linear {
+                    min: -1.0,
+                    max: 1.0,
+                },
+            ),
+        }
+    }
+}
+
+impl Default for SequencerParams {
+    fn default() -> Self {
+        Self {
+            enabled: BoolParam::new("Sequencer", false),
+
+            length: IntParam::new(
+                "Steps",
+                16,
+                IntRange::Linear {
+                    min: 16,
+                    max: sequencer::MAX_STEPS as i32,
+                },
+            ),
+
+            rate: EnumParam::new("Step Rate", NoteDivision::Sixteenth),
+
+            tempo: FloatParam::new(
+                "Sequencer Tempo",
+                120.0,
+                FloatRange::Linear {
+                    min: 40.0,
+                    max: 300.0,
+                },
+            )
+            .with_step_size(0.1)
+            .with_unit(" BPM"),
+
+            accent: FloatParam::new(
+                "Accent",
+                4.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: 12.0,
+                },
+            )
+            .with_unit(" dB"),
+
+            flam_time: FloatParam::new(
+                "Flam Time",
+                0.02,
+                FloatRange::Linear {
+                    min: 0.005,
+                    max: 0.05,
+                },
+            )
+            .with_unit(" s"),
+
+            pattern: Arc::new(RwLock::new(Pattern::default())),
+            pattern_generation: Arc::new(AtomicU32::new(0)),
+        }
+    }
+}
+
+impl Default for RatchetParams {
+    fn default() -> Self {
+        Self {
+            mode: EnumParam::new("Ratchet", RatchetMode::Off),
+
+            grace_time: FloatParam::new(
+                "Grace Time",
+                0.02,
+                FloatRange::Linear {
+                    min: 0.005,
+                    max: 0.06,
+                },
+            )
+            .with_unit(" s"),
+
+            grace_level: FloatParam::new(
+                "Grace Level",
+                50.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: 100.0,
+                },
+            )
+            .with_unit(" %"),
+
+            roll_rate: EnumParam::new("Roll Rate", NoteDivision::ThirtySecond),
+
+            roll_count: IntParam::new(
+                "Roll Hits",
+                4,
+                IntRange::Linear {
+                    min: 2,
+                    max: MAX_RATCHET_HITS as i32,
+                },
+            ),
+
+            roll_ramp: FloatParam::new(
+                "Roll Ramp",
+                0.0,
+                FloatRange::Linear {
+                    min: -100.0,
+                    max: 100.0,
+                },
+            )
+            .with_unit(" %"),
+        }
+    }
+}
+
+impl Default for DrumSynthParams {
+    fn default() -> Self {
+        Self {
+            gain: FloatParam::new(
+                "Gain",
+                -6.0,
+                FloatRange::Linear {
+                    min: -30.0,
+                    max: 6.0,
+                },
+            )
+            .with_smoother(SmoothingStyle::Logarithmic(50.0))
+            .with_step_size(0.01)
+            .with_unit(" dB"),
+            
+            velocity_sensitivity: FloatParam::new(
+                "Velocity Sensitivity",
+                100.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: 100.0,
+                },
+            )
+            .with_unit(" %"),
+            
+            impact_params: ImpactParams::default(),
+            tuning_params: TuningParams::default(),
+            snare_params: SnareParams::default(),
+            dynamics_params: DynamicsParams::default(),
+            humanize_params: HumanizeParams::default(),
+            noise_params: NoiseParams::default(),
+            quality_params: QualityParams::default(),
+            morph_params: MorphParams::default(),
+            lfo_params: std::array::from_fn(|_| LfoParams::default()),
+            mod_envelope_params: std::array::from_fn(|_| ModEnvelopeParams::default()),
+            mod_slot_params: std::array::from_fn(|_| ModSlotParams::default()),
+            sequencer_params: SequencerParams::default(),
+            ratchet_params: RatchetParams::default(),
+            state_version: Arc::new(AtomicU32::new(migrations::STATE_VERSION)),
+            random_locks: Arc::new(RwLock::new(Vec::new())),
+        }
+    }
+}
+
+/// Smoothed parameter values for one output sample, shared by all of its oversampled steps
+struct ParamFrame {
+    transient_level: f32,
+    delay_samples: f32,
+    feedback: f32,
+    resonance_level: f32,
+    snare_level: f32,
+}
+
+impl DrumEngine {
+    /// `tone` scales every band's frequency, used for humanization. `value` returns a parameter's
+    /// current value.
+    fn configure_eq(
+        eq: &mut LayerEQ,
+        params: &LayerEqParams,
+        value: &impl Fn(&FloatParam) -> f32,
+        overrides: &ParamOverrides,
+        tone: f32,
+        sample_rate: f32,
+    ) {
+        eq.low_cut.configure_fixed_q(
+            overrides.bool(&params.low_cut_enabled),
+            value(&params.low_cut_freq) * tone,
+            0.0,
+            sample_rate
+        );
+        eq.low_shelf.configure_fixed_q(
+            overrides.bool(&params.low_shelf_enabled),
+            value(&params.low_shelf_freq) * tone,
+            value(&params.low_shelf_gain),
+            sample_rate
+        );
+        eq.peak.configure(
+            overrides.bool(&params.peak_enabled),
+            value(&params.peak_freq) * tone,
+            value(&params.peak_gain),
+            value(&params.peak_q),
+            sample_rate
+        );
+        eq.peak2.configure(
+            overrides.bool(&params.peak2_enabled),
+            value(&params.peak2_freq) * tone,
+            value(&params.peak2_gain),
+            value(&params.peak2_q),
+            sample_rate
+        );
+        eq.high_shelf.configure_fixed_q(
+            overrides.bool(&params.high_shelf_enabled),
+            value(&params.high_shelf_freq) * tone,
+            value(&params.high_shelf_gain),
+            sample_rate
+        );
+        eq.high_cut.configure_fixed_q(
+            overrides.bool(&params.high_cut_enabled),
+            value(&params.high_cut_freq) * tone,
+            0.0,
+            sample_rate
         );
+    }
+    
+    /// Roll the variation for an incoming note and schedule its hit `delay` samples from now.
+    /// `gain` is the hit's level, from its velocity and the ratchet or accent.
+    fn note_on(&mut self, gain: f32, delay: u32) {
+        let humanize = &self.params.humanize_params;
+        let overrides = &self.overrides;
+        
+        // Round robin variations are fixed per slot, otherwise every hit gets new ones
+        let round_robin = overrides.int(&humanize.round_robin) as u32;
+        let rng = if round_robin > 1 {
+            self.round_robin_pos = (self.round_robin_pos + 1) % round_robin;
+            self.round_robin_rng.seed(ROUND_ROBIN_SEED + self.round_robin_pos as u64);
+            &mut self.round_robin_rng
+        } else {
+            self.round_robin_pos = 0;
+            &mut self.humanize_rng
+        };
+        
+        let variation = HitVariation {
+            velocity: gain,
+            tension: 1.0 + rng.next_bipolar() * overrides.float(&humanize.tension) / 100.0,
+            damping: rng.next_bipolar() * overrides.float(&humanize.damping),
+            level: util::db_to_gain(rng.next_bipolar() * overrides.float(&humanize.level)),
+            tone: 2.0_f32.powf(rng.next_bipolar() * overrides.float(&humanize.tone) / 12.0),
+        };
+        
+        // Hits can only be pushed back, never pulled forward
+        let samples_left = delay + (rng.next_f32() * overrides.float(&humanize.timing) * self.sample_rate) as u32;
+        
+        // Hits past the queue's capacity are dropped
+        if let Some(slot) = self.pending_hits.iter_mut().find(|slot| slot.is_none()) {
+            *slot = Some(PendingHit { samples_left, variation });
+        }
+    }
+    
+    /// Schedule the hits for an incoming MIDI note, which the ratchet may turn into several.
+    /// `velocity` goes from 0.0 to 1.0 like a MIDI note's.
+    fn play_note(&mut self, velocity: f32, tempo: f64) {
+        let sensitivity = self.overrides.float(&self.params.velocity_sensitivity) / 100.0;
+        let velocity = 1.0 - sensitivity * (1.0 - velocity);
+        
+        let mut hits = [(0.0, 0); MAX_RATCHET_HITS];
+        let mut count = 0;
+        self.ratchet_hold = ratchet::schedule(
+            &self.params.ratchet_params,
+            &self.overrides,
+            tempo,
+            self.sample_rate,
+            |gain, delay| {
+                if count < MAX_RATCHET_HITS {
+                    hits[count] = (gain, delay);
+                    count += 1;
+                }
+            },
+        );
+        
+        for (gain, delay) in hits.into_iter().take(count) {
+            self.note_on(velocity * gain, delay);
+        }
+    }
+    
+    /// End a MIDI note. Hits that haven't started yet are released after the last one.
+    fn end_note(&mut self) {
+        let last_hit = self.pending_hits.iter().flatten().map(|hit| hit.samples_left).max();
+        match last_hit {
+            Some(samples_left) => self.release_gate.start(samples_left + self.ratchet_hold),
+            None => self.note_off(),
+        }
+    }
+    
+    fn note_off(&mut self) {
+        self.transient_envelope.note_off();
+        // Keep the wires' note length the same when they start late
+        self.snare_stop.start(self.snare_delay_samples());
+    }
+    
+    /// Schedule the hits for a sequencer step. The step's velocity is part of the pattern, so
+    /// unlike a MIDI note's it doesn't depend on the velocity sensitivity.
+    fn play_step(&mut self, step: StepTrigger) {
+        let sequencer = &self.params.sequencer_params;
+        let accent = if step.accent { util::db_to_gain(self.overrides.float(&sequencer.accent)) } else { 1.0 };
+        let gain = step.velocity * accent;
+        
+        // The grace note lands on the step and the hit follows it, at the ratchet's grace level
+        let mut delay = 0;
+        if step.flam {
+            delay = (self.overrides.float(&sequencer.flam_time) * self.sample_rate) as u32;
+            let grace_level = self.overrides.float(&self.params.ratchet_params.grace_level) / 100.0;
+            self.note_on(gain * grace_level, 0);
+        }
+        self.note_on(gain, delay);
+        
+        if !self.midi_note_held {
+            self.release_gate.start(delay + (self.sequencer.step_samples() / 2.0) as u32);
+        }
+    }
+    
+    /// Start a hit right now
+    fn trigger_hit(&mut self, variation: HitVariation) {
+        self.hit_variation = variation;
+        self.is_playing = true;
+        self.silent_samples = 0;
+        // The variation changes the filters' frequencies and the damping
+        self.filters_stale = true;
+        
+        // Trigger envelopes
+        self.seed_noise();
+        self.transient_envelope.note_on();
+        self.snare_start.start(self.snare_delay_samples());
+        self.snare_stop.cancel();
+        self.modulation.trigger();
+        self.pitch_envelope.note_on();
+    }
+    
+    /// The snare wires' start delay in samples at the oversampled rate
+    fn snare_delay_samples(&self) -> u32 {
+        let rate = self.sample_rate * self.oversampling.factor() as f32;
+        (self.value(&self.params.snare_params.delay) * rate) as u32
+    }
+    
+    /// Reseed both noise layers for a new hit
+    fn seed_noise(&mut self) {
+        let (transient_seed, snare_seed) = match self.overrides.enumeration(&self.params.noise_params.seed_mode) {
+            NoiseSeedMode::RandomPerHit => (self.seed_rng.next_u32() as u64, self.seed_rng.next_u32() as u64),
+            NoiseSeedMode::FixedPerHit => {
+                // Every round robin variation and every seeded render gets its own noise
+                let seed = (self.overrides.int(&self.params.noise_params.seed) as u64 + self.round_robin_pos as u64)
+                    .wrapping_add(self.render_seed.wrapping_mul(RENDER_SEED_STRIDE));
+                (seed, seed + SNARE_SEED_OFFSET)
+            }
+        };
+        
+        self.transient_noise.seed(transient_seed);
+        self.snare_noise.seed(snare_seed);
+    }
+    
+    /// The oversampling factor for the current processing mode
+    fn active_oversampling(&self) -> OversamplingFactor {
+        match self.process_mode {
+            ProcessMode::Offline => self.overrides.enumeration(&self.params.quality_params.offline_oversampling),
+            _ => self.overrides.enumeration(&self.params.quality_params.oversampling),
+        }
+    }
+    
+    /// Switch the synthesis to a new oversampling factor
+    fn set_oversampling(&mut self, oversampling: OversamplingFactor) {
+        self.oversampling = oversampling;
+        self.decimator.set_oversampling(oversampling);
+        
+        let rate = self.sample_rate * oversampling.factor() as f32;
+        self.transient_envelope.sample_rate = rate;
+        self.noise_envelope.sample_rate = rate;
+        self.transient_noise.set_sample_rate(rate, oversampling.factor());
+        self.snare_noise.set_sample_rate(rate, oversampling.factor());
+        
+        // Old contents would ring at the wrong pitch
+        self.resonance_buffer.fill(0.0);
+    }
+    
+    /// Follow changes to the oversampling factor and the limiter switch, returns true if the
+    /// latency changed. Only called between blocks.
+    fn update_latency(&mut self) -> bool {
+        let latency = self.latency();
+        let oversampling = self.active_oversampling();
+        if oversampling != self.oversampling {
+            self.set_oversampling(oversampling);
+        }
+        self.limiter
+            .set_enabled(self.overrides.bool(&self.params.dynamics_params.limiter_enabled));
+        
+        self.latency() != latency
+    }
+    
+    /// Total latency of the decimation filters and the limiter's lookahead
+    fn latency(&self) -> u32 {
+        self.decimator.latency() + self.limiter.latency()
+    }
+    
+    /// A float parameter's value, or its morphed value while morphing
+    fn value(&self, param: &FloatParam) -> f32 {
+        self.morph.value(param, &self.overrides)
+    }
+    
+    /// Get ready for processing at a new sample rate, shared by the plugin and offline rendering
+    fn prepare(&mut self, sample_rate: f32, process_mode: ProcessMode) {
+        self.sample_rate = sample_rate;
+        self.process_mode = process_mode;
+        self.set_oversampling(self.active_oversampling());
+        
+        // The snapshots and the pattern may have been replaced by loading a state
+        self.morph.refresh(&self.params.morph_params);
+        self.sequencer.refresh(&self.params.sequencer_params);
+        
+        // Configure EQs and the loop filter with initial values
+        self.configure_filters();
+
+        self.transient_shaper.set_sample_rate(sample_rate);
+        self.compressor.set_sample_rate(sample_rate);
+        self.limiter.set_sample_rate(sample_rate);
+        self.limiter
+            .set_enabled(self.overrides.bool(&self.params.dynamics_params.limiter_enabled));
+        self.modulation.set_sample_rate(sample_rate);
+        self.pitch_envelope.sample_rate = sample_rate;
+    }
+    
+    /// Read the parameters for the next block. The sequencer runs on its own clock with
+    /// `internal_clock`, otherwise it follows `transport`.
+    fn begin_block(&mut self, transport: TransportInfo, internal_clock: bool) {
+        self.morph.update(&self.params.morph_params);
+        // Automation splits the host's blocks, so this keeps the filters sample accurate
+        self.filters_stale = true;
+        
+        self.modulation.configure(
+            &self.params.lfo_params,
+            &self.params.mod_envelope_params,
+            &self.params.mod_slot_params,
+            &self.overrides,
+            transport,
+            self.sample_rate,
+        );
+        self.sequencer.configure(
+            &self.params.sequencer_params,
+            &self.overrides,
+            transport,
+            internal_clock,
+            self.sample_rate,
+        );
+        
+        // Update ADSR parameters
+        self.transient_envelope.set_parameters(
+            self.value(&self.params.impact_params.attack),
+            self.value(&self.params.impact_params.decay),
+            0.0, // -inf sustain for transient
+            self.value(&self.params.impact_params.release),
+            self.value(&self.params.impact_params.hold),
+        );
+        
+        self.pitch_envelope.set_parameters(
+            self.value(&self.params.tuning_params.pitch_amount),
+            self.value(&self.params.tuning_params.pitch_decay),
+            self.value(&self.params.tuning_params.pitch_curve),
+        );
+        
+        self.transient_envelope.set_curves(
+            self.value(&self.params.impact_params.attack_curve),
+            self.value(&self.params.impact_params.decay_curve),
+            self.value(&self.params.impact_params.release_curve),
+        );
+        
+        self.noise_envelope.set_parameters(
+            self.value(&self.params.snare_params.attack),
+            self.value(&self.params.snare_params.decay),
+            self.value(&self.params.snare_params.sustain),
+            self.value(&self.params.snare_params.release),
+            self.value(&self.params.snare_params.hold),
+        );
+        self.noise_envelope.set_curves(
+            self.value(&self.params.snare_params.attack_curve),
+            self.value(&self.params.snare_params.decay_curve),
+            self.value(&self.params.snare_params.release_curve),
+        );
+        
+        self.transient_noise.set_color(self.overrides.enumeration(&self.params.impact_params.noise_color));
+        self.snare_noise.set_color(self.overrides.enumeration(&self.params.snare_params.noise_color));
+        
+        // Update master dynamics
+        let (dynamics, overrides) = (&self.params.dynamics_params, &self.overrides);
+        self.transient_shaper.set_parameters(
+            overrides.float(&dynamics.shaper_attack) / 100.0,
+            overrides.float(&dynamics.shaper_sustain) / 100.0,
+        );
+        self.compressor.set_parameters(
+            overrides.float(&dynamics.comp_threshold),
+            overrides.float(&dynamics.comp_ratio),
+            overrides.float(&dynamics.comp_attack),
+            overrides.float(&dynamics.comp_release),
+            overrides.float(&dynamics.comp_makeup),
+            overrides.float(&dynamics.comp_mix),
+        );
+        self.limiter.set_ceiling(overrides.float(&dynamics.limiter_ceiling));
+        
+        // Rolls follow the host's tempo, or the sequencer's clock without one
+        self.tempo = match transport.tempo {
+            Some(tempo) if !internal_clock => tempo,
+            _ => self.overrides.float(&self.params.sequencer_params.tempo) as f64,
+        };
+    }
+    
+    fn handle_event(&mut self, event: NoteEvent<()>) {
+        match event {
+            NoteEvent::NoteOn { note, velocity, .. } => {
+                self.midi_note_id = note;
+                self.midi_note_freq = util::midi_note_to_freq(note);
+                self.midi_note_held = true;
+                // The note's own NoteOff releases it, not the sequencer
+                self.release_gate.cancel();
+                self.play_note(velocity, self.tempo);
+            }
+            NoteEvent::NoteOff { note, .. } if note == self.midi_note_id => {
+                self.midi_note_held = false;
+                self.end_note();
+            }
+            _ => (),
+        }
+    }
+    
+    /// Render a stretch of samples without any notes or automation in it, the filters start it
+    /// with up to date coefficients
+    fn render_block(&mut self, output: &mut [f32]) {
+        self.filters_stale = true;
+        for sample in output {
+            *sample = self.render_sample();
+        }
+    }
+    
+    /// Render the next output sample
+    fn render_sample(&mut self) -> f32 {
+        let factor = self.oversampling.factor();
+        let mut oversampled = [0.0; MAX_OVERSAMPLING];
+        
+        if let Some(step) = self.sequencer.next_sample() {
+            self.play_step(step);
+        }
+        if self.release_gate.tick() {
+            self.note_off();
+        }
+        
+        // Start waiting hits once their timing offset has passed
+        for index in 0..MAX_PENDING_HITS {
+            if let Some(pending) = &mut self.pending_hits[index] {
+                if pending.samples_left == 0 {
+                    let variation = pending.variation;
+                    self.pending_hits[index] = None;
+                    self.trigger_hit(variation);
+                } else {
+                    pending.samples_left -= 1;
+                }
+            }
+        }
+        
+        if self.is_silent() {
+            self.skipped_samples += 1;
+            return 0.0;
+        }
+        self.catch_up();
+        
+        // Process each layer - snare feeds through resonator per Karplus-Strong
+        let frame = self.next_frame();
+        for sample in oversampled.iter_mut().take(factor) {
+            let transient_output = self.process_transient(&frame);
+            let snare_output = self.process_snare_input(&frame);
+            let resonance_output = self.process_resonance(&frame, transient_output, snare_output);
+        
+            // Resonance output contains both transient and snare processed through delay
+            *sample = transient_output + resonance_output;
+        }
+        
+        let output = self.decimator.process(&mut oversampled[..factor])
+            * util::db_to_gain_fast(self.overrides.smoothed(&self.params.gain));
+        
+        // Master dynamics: shape the hit, glue it, then make sure it never clips
+        let output = self.transient_shaper.process(output);
+        let output = self.compressor.process(output);
+        let output = self.limiter.process(output);
+        
+        // Check if we're still active
+        self.is_playing = self.transient_envelope.is_active()
+            || self.noise_envelope.is_active()
+            || self.snare_start.samples_left.is_some();
+        
+        output
+    }
+    
+    /// Whether the output has died down completely, nothing needs to be synthesized until the
+    /// next hit
+    fn is_silent(&self) -> bool {
+        !self.is_playing && self.silent_samples > self.latency()
+    }
+    
+    /// Whether new hits can start without any further notes coming in
+    fn has_scheduled_hits(&self) -> bool {
+        self.sequencer.is_running()
+            || self.release_gate.samples_left.is_some()
+            || self.pending_hits.iter().any(Option::is_some)
+    }
+    
+    /// Measure the resonator after a block of `samples` samples to see if the plugin went silent
+    fn update_activity(&mut self, samples: u32) {
+        if self.is_silent() {
+            self.silent_samples = self.silent_samples.saturating_add(samples);
+            self.catch_up();
+            return;
+        }
+        
+        // Anything further back in the delay line won't be read again
+        let span = (self.resonance_span + 2).min(MAX_DELAY);
+        let start = (self.resonance_write_pos + MAX_DELAY - span) % MAX_DELAY;
+        let (older, newer) = self.resonance_buffer.split_at(start);
+        self.resonance_peak = newer.iter().chain(older).take(span).fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+        self.resonance_span = 0;
+        if self.is_playing || self.resonance_peak >= SILENCE_LEVEL {
+            self.silent_samples = 0;
+        } else {
+            self.silent_samples = self.silent_samples.saturating_add(samples);
+        }
+    }
+    
+    /// Samples until the output is silent, from the resonator's level and how much of it is
+    /// lost every time the signal goes around the delay line
+    fn tail_samples(&self) -> u32 {
+        let latency = self.latency().saturating_sub(self.silent_samples);
+        if self.resonance_peak < SILENCE_LEVEL {
+            return latency;
+        }
+        
+        // The loop filter only ever takes energy away, so the feedback alone gives the longest
+        // the resonator can ring for
+        let loop_gain = self.value(&self.params.tuning_params.feedback).abs().min(0.999);
+        let loop_samples = self.value(&self.params.tuning_params.delay_samples) * self.hit_variation.tension;
+        let loops = (SILENCE_LEVEL / self.resonance_peak).ln() / loop_gain.ln();
+        let decay = (loops * loop_samples).min(MAX_TAIL_SECONDS * self.sample_rate);
+        
+        decay as u32 + latency
+    }
+    
+    /// Tell the host whether it can suspend the plugin. It keeps running while notes or steps
+    /// can still start hits, and reports the resonator's tail once they're all done.
+    fn process_status(&self) -> ProcessStatus {
+        if self.is_playing || self.has_scheduled_hits() {
+            ProcessStatus::KeepAlive
+        } else if self.is_silent() {
+            ProcessStatus::Normal
+        } else {
+            ProcessStatus::Tail(self.tail_samples())
+        }
+    }
+    
+    /// Advance the smoothers and mod sources over the samples skipped while silent, so they
+    /// pick up where they would have been had every sample been synthesized
+    fn catch_up(&mut self) {
+        let steps = std::mem::take(&mut self.skipped_samples);
+        if steps == 0 {
+            return;
+        }
+        
+        let (morph, overrides, params) = (&self.morph, &self.overrides, &self.params);
+        let amount = overrides.smoothed_steps(&params.morph_params.amount, steps);
+        for param in [
+            &params.impact_params.level,
+            &params.tuning_params.delay_samples,
+            &params.tuning_params.feedback,
+            &params.tuning_params.level,
+            &params.snare_params.level,
+        ] {
+            morph.smoothed(param, overrides, steps);
+        }
+        overrides.smoothed_steps(&params.gain, steps);
+        
+        self.morph.set_amount(amount / 100.0);
+        self.modulation.skip(steps);
+        // The filters' smoothers catch up the next time they're configured
+        self.filter_steps += steps;
+        self.filters_stale = true;
+    }
+    
+    /// Advance the smoothers by one output sample. The filters are updated every
+    /// `FILTER_UPDATE_INTERVAL` samples, on every sample while they're modulated or morphed, or
+    /// right away after a new block or hit.
+    fn next_frame(&mut self) -> ParamFrame {
+        let variation = self.hit_variation;
+        let morphing = self.params.morph_params.amount.smoothed.is_smoothing();
+        self.morph.set_amount(self.overrides.smoothed(&self.params.morph_params.amount) / 100.0);
+        self.modulation.next_sample();
+        
+        self.filter_steps += 1;
+        if self.filters_stale
+            || morphing
+            || self.modulation.modulates_filters()
+            || self.filter_steps >= FILTER_UPDATE_INTERVAL
+        {
+            self.configure_filters();
+        }
+        
+        // Morphing replaces a parameter's value, modulation is applied on top of that
+        let (morph, modulation, overrides) = (&self.morph, &self.modulation, &self.overrides);
+        let value = |param: &FloatParam| modulation.apply(param, morph.smoothed(param, overrides, 1));
+        
+        ParamFrame {
+            transient_level: value(&self.params.impact_params.level) * variation.level * variation.velocity,
+            // Higher pitch means a shorter delay
+            delay_samples: value(&self.params.tuning_params.delay_samples)
+                * variation.tension
+                * 2.0_f32.powf(-self.pitch_envelope.process() / 12.0),
+            feedback: value(&self.params.tuning_params.feedback),
+            resonance_level: value(&self.params.tuning_params.level),
+            snare_level: value(&self.params.snare_params.level) * variation.velocity,
+        }
+    }
+    
+    /// Update the EQs and the loop filter, catching their smoothers up on every sample since the
+    /// last update. The filters only recalculate their coefficients if something changed.
+    fn configure_filters(&mut self) {
+        let rate = self.sample_rate * self.oversampling.factor() as f32;
+        let steps = self.filter_steps;
+        self.filter_steps = 0;
+        self.filters_stale = false;
+        
+        let variation = self.hit_variation;
+        let (morph, modulation, overrides) = (&self.morph, &self.modulation, &self.overrides);
+        let value = |param: &FloatParam| modulation.apply(param, morph.smoothed(param, overrides, steps));
+        
+        let params = &self.params;
+        Self::configure_eq(&mut self.transient_eq, &params.impact_params.eq, &value, overrides, variation.tone, rate);
+        Self::configure_eq(&mut self.snare_eq, &params.snare_params.eq, &value, overrides, variation.tone, rate);
+        Self::configure_eq(&mut self.resonance_eq, &params.tuning_params.eq, &value, overrides, variation.tone, rate);
+        
+        self.resonance_filter.configure(
+            overrides.enumeration(&params.tuning_params.loop_filter),
+            (value(&params.tuning_params.damping) + variation.damping).clamp(0.0, 0.95),
+            value(&params.tuning_params.stiffness),
+            self.sample_rate,
+            self.oversampling.factor(),
+        );
+    }
+    
+    fn process_transient(&mut self, frame: &ParamFrame) -> f32 {
+        // Use a noise burst for transient (not square wave)
+        let noise = self.transient_noise.next_sample();
+        
+        // Apply envelope to transient
+        let envelope = self.transient_envelope.process();
         
         // Apply EQ and level control
-        let output = noise * envelope * self.params.impact_params.level.smoothed.next();
+        let output = noise * envelope * frame.transient_level;
         self.transient_eq.process(output)
     }
     
-    fn process_snare_input(&mut self) -> f32 {
+    fn process_snare_input(&mut self, frame: &ParamFrame) -> f32 {
         // Generate noise for snare wires
-        let noise = Self::calculate_noise();
+        let noise = self.snare_noise.next_sample();
+        
+        if self.snare_start.tick() {
+            self.noise_envelope.note_on();
+        }
+        if self.snare_stop.tick() {
+            self.noise_envelope.note_off();
+        }
         
         // Apply envelope
         let envelope = self.noise_envelope.process();
         
-        // Configure snare EQ (for the 2kHz bump)
-        self.snare_eq.configure(
-            self.params.snare_params.eq_freq.smoothed.next(), 
-            self.params.snare_params.eq_gain.smoothed.next(),
-            self.params.snare_params.eq_q.smoothed.next(),
-            self.sample_rate
-        );
-        
         // Apply EQ and level control
-        let output = noise * envelope * self.params.snare_params.level.smoothed.next();
+        let output = noise * envelope * frame.snare_level;
         self.snare_eq.process(output)
     }
     
-    fn process_resonance(&mut self, transient_output: f32, snare_output: f32) -> f32 {
-        // Get delay samples directly (not time-based)
-        let delay_samples = self.params.tuning_params.delay_samples.smoothed.next() as usize;
+    fn process_resonance(&mut self, frame: &ParamFrame, transient_output: f32, snare_output: f32) -> f32 {
+        // Tension is in samples at the host rate, the dispersion stage adds delay we compensate for
+        let loop_delay = frame.delay_samples * self.oversampling.factor() as f32;
+        self.resonance_filter.set_loop(loop_delay, frame.feedback < 0.0);
+        let delay_samples = loop_delay - self.resonance_filter.dispersion_delay();
         
         // Ensure delay is within buffer size
-        let delay_samples = delay_samples.min(MAX_DELAY - 1);
-        
-        // Set read position based on current write position and delay
-        self.resonance_read_pos = (self.resonance_write_pos + MAX_DELAY - delay_samples) % MAX_DELAY;
+        let delay_samples = delay_samples.clamp(1.0, (MAX_DELAY - 2) as f32);
+        self.resonance_span = self.resonance_span.max(delay_samples as usize);
         
-        // Read from delay buffer at the delayed position
-        let delayed_sample = self.resonance_buffer[self.resonance_read_pos];
+        // Read from delay buffer at the delayed position, interpolating between samples
+        let read_pos = (self.resonance_write_pos + MAX_DELAY) as f32 - delay_samples;
+        let read_index = read_pos as usize;
+        let frac = read_pos - read_index as f32;
+        let older = self.resonance_buffer[read_index % MAX_DELAY];
+        let newer = self.resonance_buffer[(read_index + 1) % MAX_DELAY];
+        let delayed_sample = older + (newer - older) * frac;
         
-        // Apply lowpass filter (damping) to feedback - key part of Karplus-Strong
-        let damping = self.params.tuning_params.damping.smoothed.next();
-        self.resonance_lowpass.set_cutoff(1.0 - damping, true);
-        let filtered_feedback = self.resonance_lowpass.process(delayed_sample);
+        // Apply loop filter (damping) to feedback - key part of Karplus-Strong
+        let filtered_feedback = self.resonance_filter.process(delayed_sample);
         
-        // Apply feedback - note the negative feedback for resonance
-        let feedback = self.params.tuning_params.feedback.smoothed.next();
-        
-        // Mix transient + snare input with filtered feedback
+        // Mix transient + snare input with filtered feedback, note the negative feedback
         // Both transient and snare noise feed into the resonator
-        let resonance_input = transient_output + snare_output + (filtered_feedback * feedback);
+        let resonance_input = transient_output + snare_output + (filtered_feedback * frame.feedback);
         
         // Write to buffer
         self.resonance_buffer[self.resonance_write_pos] = resonance_input;
@@ -705,20 +2447,141 @@ impl DrumSynth {
         // Update write position
         self.resonance_write_pos = (self.resonance_write_pos + 1) % MAX_DELAY;
         
-        // Configure resonance EQ
-        self.resonance_eq.configure(
-            self.params.tuning_params.eq_freq.smoothed.next(), 
-            self.params.tuning_params.eq_gain.smoothed.next(),
-            self.params.tuning_params.eq_q.smoothed.next(),
-            self.sample_rate
-        );
-        
         // Apply EQ and level control
-        let output = resonance_input * self.params.tuning_params.level.smoothed.next();
+        let output = resonance_input * frame.resonance_level;
         self.resonance_eq.process(output)
     }
 }
 
+impl DrumEngine {
+    /// An engine playing the default patch at `sample_rate`
+    pub fn new(sample_rate: f32) -> Self {
+        let mut engine = Self::default();
+        engine.prepare(sample_rate, ProcessMode::Realtime);
+        engine.reset();
+        
+        engine
+    }
+    
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.prepare(sample_rate, ProcessMode::Realtime);
+    }
+
+    /// Play `patch` from now on, hits that are still ringing carry on with its values.
+    ///
+    /// This allocates, so it must not be called on the audio thread. Set the patch before the
+    /// engine is handed to the audio callback, or use `set_prepared_patch()` there instead.
+    pub fn set_patch(&mut self, patch: &Patch) {
+        self.overrides.load(&self.params, &patch.preset);
+    }
+
+    /// Like `set_patch()`, but doesn't allocate so it can be called on the audio thread. The
+    /// patch is prepared up front on another thread.
+    pub fn set_prepared_patch(&mut self, patch: &PreparedPatch) {
+        self.overrides.set(&patch.values);
+    }
+
+    /// Make the random parts of the following hits repeatable: the noise, the humanization and
+    /// the round robin slot. Different seeds give different variations, even for a fixed noise
+    /// seed without round robins. Without a seed every engine picks its own noise, while two
+    /// engines playing the same patch and hits with the same seed render the same samples.
+    pub fn seed(&mut self, seed: u64) {
+        self.seed_rng.seed(seed);
+        self.render_seed = seed;
+        self.humanize_rng.seed(ROUND_ROBIN_SEED + seed);
+        self.round_robin_pos = seed as u32;
+    }
+
+    /// Start a hit at the beginning of the next `render()`, `velocity` goes from 0.0 to 1.0
+    pub fn trigger(&mut self, note: u8, velocity: f32) {
+        self.handle_event(NoteEvent::NoteOn {
+            timing: 0,
+            voice_id: None,
+            channel: 0,
+            note,
+            velocity,
+        });
+    }
+    
+    /// Release the last triggered note, if it's `note`. This ends the snare wires' sustain and
+    /// cuts rolls short.
+    pub fn release(&mut self, note: u8) {
+        self.handle_event(NoteEvent::NoteOff {
+            timing: 0,
+            voice_id: None,
+            channel: 0,
+            note,
+            velocity: 0.0,
+        });
+    }
+    
+    /// Render the next mono samples. The step sequencer runs on its own clock when the patch
+    /// switches it on.
+    pub fn render(&mut self, output: &mut [f32]) {
+        let transport = TransportInfo {
+            tempo: None,
+            pos_beats: None,
+        };
+        
+        for block in output.chunks_mut(BLOCK_SIZE) {
+            self.update_latency();
+            self.begin_block(transport, true);
+            self.render_block(block);
+            self.update_activity(block.len() as u32);
+        }
+    }
+    
+    /// Whether the engine is still making sound or has hits coming up. When it isn't, rendering
+    /// only produces silence until the next `trigger()`.
+    pub fn is_active(&self) -> bool {
+        !self.is_silent() || self.has_scheduled_hits()
+    }
+    
+    /// How many samples the output lags behind a `trigger()`, from oversampling and the
+    /// limiter's lookahead while the limiter is enabled
+    pub fn latency_samples(&self) -> u32 {
+        self.latency()
+    }
+    
+    /// Stop all sound and clear the filters and delay lines
+    pub fn reset(&mut self) {
+        self.midi_note_id = 0;
+        self.midi_note_freq = 1.0;
+        self.midi_note_held = false;
+        self.is_playing = false;
+        self.resonance_peak = 0.0;
+        self.resonance_span = 0;
+        self.silent_samples = 0;
+        self.pending_hits = [None; MAX_PENDING_HITS];
+        self.release_gate.cancel();
+        self.sequencer.reset();
+        self.hit_variation = HitVariation::NONE;
+        self.transient_envelope.state = ADSRState::Idle;
+        self.noise_envelope.state = ADSRState::Idle;
+        self.snare_start.cancel();
+        self.snare_stop.cancel();
+        self.pitch_envelope.position = 1.0;
+        
+        // Reset filter states
+        self.transient_eq.reset();
+        self.resonance_eq.reset();
+        self.resonance_filter.reset();
+        self.snare_eq.reset();
+        
+        self.transient_shaper.reset();
+        self.compressor.reset();
+        self.limiter.reset();
+        self.modulation.reset();
+        
+        // Clear resonance buffer
+        for sample in &mut self.resonance_buffer {
+            *sample = 0.0;
+        }
+        self.resonance_write_pos = 0;
+        self.decimator.reset();
+    }
+}
+
 impl Plugin for DrumSynth {
     const NAME: &'static str = "Drum Synth";
     const VENDOR: &'static str = "r-cha";
@@ -739,70 +2602,42 @@ impl Plugin for DrumSynth {
     const SAMPLE_ACCURATE_AUTOMATION: bool = true;
 
     type SysExMessage = ();
-    type BackgroundTask = ();
+    type BackgroundTask = DrumSynthTask;
+
+    fn filter_state(state: &mut PluginState) {
+        migrations::migrate(state);
+    }
 
     fn initialize(
         &mut self,
         _audio_io_layout: &AudioIOLayout,
         buffer_config: &BufferConfig,
-        _context: &mut impl InitContext<Self>,
+        context: &mut impl InitContext<Self>,
     ) -> bool {
-        self.sample_rate = buffer_config.sample_rate;
-        self.transient_envelope.sample_rate = buffer_config.sample_rate;
-        self.noise_envelope.sample_rate = buffer_config.sample_rate;
-        
-        // Configure EQs with initial values
-        self.transient_eq.configure(
-            self.params.impact_params.eq_freq.value(),
-            self.params.impact_params.eq_gain.value(),
-            self.params.impact_params.eq_q.value(),
-            buffer_config.sample_rate
-        );
-        
-        self.resonance_eq.configure(
-            self.params.tuning_params.eq_freq.value(),
-            self.params.tuning_params.eq_gain.value(),
-            self.params.tuning_params.eq_q.value(),
-            buffer_config.sample_rate
-        );
-        
-        self.snare_eq.configure(
-            self.params.snare_params.eq_freq.value(),
-            self.params.snare_params.eq_gain.value(),
-            self.params.snare_params.eq_q.value(),
-            buffer_config.sample_rate
-        );
+        self.engine.prepare(buffer_config.sample_rate, buffer_config.process_mode);
+        context.set_latency_samples(self.engine.latency());
 
         true
     }
 
     fn params(&self) -> Arc<dyn Params> {
-        self.params.clone()
+        self.engine.params.clone()
     }
     
-    fn editor(&mut self, _async_executor: AsyncExecutor<Self>) -> Option<Box<dyn Editor>> {
-        ui::default_editor(self.params.clone(), self.editor_state.clone())
+    fn task_executor(&mut self) -> TaskExecutor<Self> {
+        Box::new(|task| match task {
+            DrumSynthTask::Bounce(bounce) => bounce.run(),
+            DrumSynthTask::PresetDialog(dialog) => dialog.run(),
+            DrumSynthTask::Reveal(path) => ui::reveal_file(&path),
+        })
+    }
+
+    fn editor(&mut self, async_executor: AsyncExecutor<Self>) -> Option<Box<dyn Editor>> {
+        ui::default_editor(self.engine.params.clone(), self.editor_state.clone(), async_executor)
     }
 
     fn reset(&mut self) {
-        self.midi_note_id = 0;
-        self.midi_note_freq = 1.0;
-        self.is_playing = false;
-        self.transient_envelope.state = ADSRState::Idle;
-        self.noise_envelope.state = ADSRState::Idle;
-        
-        // Reset filter states
-        self.transient_eq.reset();
-        self.resonance_eq.reset();
-        self.resonance_lowpass.reset();
-        self.snare_eq.reset();
-        
-        // Clear resonance buffer
-        for sample in &mut self.resonance_buffer {
-            *sample = 0.0;
-        }
-        self.resonance_write_pos = 0;
-        self.resonance_read_pos = 0;
+        self.engine.reset();
     }
 
     fn process(
@@ -811,71 +2646,53 @@ impl Plugin for DrumSynth {
         _aux: &mut AuxiliaryBuffers,
         context: &mut impl ProcessContext<Self>,
     ) -> ProcessStatus {
+        let engine = &mut self.engine;
         let mut next_event = context.next_event();
         
-        // Update ADSR parameters
-        self.transient_envelope.set_parameters(
-            self.params.impact_params.attack.value(),
-            self.params.impact_params.decay.value(),
-            0.0, // -inf sustain for transient
-            self.params.impact_params.release.value(),
-            self.params.impact_params.hold.value(),
-        );
+        // The oversampling factor and the limiter change the latency, so they only switch
+        // between blocks
+        if engine.update_latency() {
+            context.set_latency_samples(engine.latency());
+        }
         
-        self.noise_envelope.set_parameters(
-            self.params.snare_params.attack.value(),
-            self.params.snare_params.decay.value(),
-            0.0, // No sustain for snare
-            self.params.snare_params.decay.value() * 0.5, // Shorter release
-            0.0, // No hold
-        );
+        let transport = context.transport();
+        let transport = TransportInfo {
+            tempo: transport.tempo,
+            pos_beats: transport.pos_beats().filter(|_| transport.playing),
+        };
+        // The standalone build has no song position to follow
+        let standalone = context.plugin_api() == PluginApi::Standalone;
+        engine.begin_block(transport, standalone);
         
-        for (sample_id, channel_samples) in buffer.iter_samples().enumerate() {
-            // Handle MIDI events
+        // The output is mono, it's rendered into the first channel and copied to the others
+        let samples = buffer.samples();
+        let Some((output, channels)) = buffer.as_slice().split_first_mut() else {
+            return ProcessStatus::Normal;
+        };
+        
+        // Automation already splits the host's blocks, this splits them further at the notes so
+        // every stretch in between is rendered in one go
+        let mut start = 0;
+        while start < samples {
             while let Some(event) = next_event {
-                if event.timing() > sample_id as u32 {
+                if event.timing() as usize > start {
                     break;
                 }
 
-                match event {
-                    NoteEvent::NoteOn { note, .. } => {
-                        self.midi_note_id = note;
-                        self.midi_note_freq = util::midi_note_to_freq(note);
-                        self.is_playing = true;
-                        
-                        // Trigger envelopes
-                        self.transient_envelope.note_on();
-                        self.noise_envelope.note_on();
-                    }
-                    NoteEvent::NoteOff { note, .. } if note == self.midi_note_id => {
-                        self.transient_envelope.note_off();
-                        self.noise_envelope.note_off();
-                    }
-                    _ => (),
-                }
-
+                engine.handle_event(event);
                 next_event = context.next_event();
             }
             
-            // Process each layer - snare feeds through resonator per Karplus-Strong
-            let transient_output = self.process_transient();
-            let snare_output = self.process_snare_input();
-            let resonance_output = self.process_resonance(transient_output, snare_output);
-            
-            // Resonance output contains both transient and snare processed through delay
-            let output = (transient_output + resonance_output) 
-                * util::db_to_gain_fast(self.params.gain.smoothed.next());
-                
-            // Apply to all channels
-            for sample in channel_samples {
-                *sample = output;
-            }
-            
-            // Check if we're still active
-            self.is_playing = self.transient_envelope.is_active() || self.noise_envelope.is_active();
+            let end = next_event.map_or(samples, |event| (event.timing() as usize).min(samples));
+            engine.render_block(&mut output[start..end]);
+            start = end;
         }
-
-        ProcessStatus::KeepAlive
+        for channel in channels {
+            channel.copy_from_slice(output);
+        }
+        
+        engine.update_activity(samples as u32);
+        engine.process_status()
     }
 }
 
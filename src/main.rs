@@ -1,6 +1,15 @@
 use drum_synth::DrumSynth;
 use nih_plug::prelude::*;
 
-fn main() {
-    nih_export_standalone::<DrumSynth>();
+fn main() -> std::process::ExitCode {
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(exit_code) = drum_synth::cli::run(&args) {
+        return exit_code;
+    }
+
+    if nih_export_standalone::<DrumSynth>() {
+        std::process::ExitCode::SUCCESS
+    } else {
+        std::process::ExitCode::FAILURE
+    }
 }
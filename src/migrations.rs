@@ -0,0 +1,194 @@
+//! Upgrades saved plugin state and preset files from older versions of the plugin
+//!
+//! Every saved state carries a `state-version` field. When a parameter's ID, range or unit
+//! changes in a way that would make old sessions sound different, bump `STATE_VERSION` and add
+//! a migration that converts the previous version's state. nih_plug stores parameters as plain
+//! values, so a range change on its own only needs a migration if the old values should map to
+//! different plain values.
+
+use nih_plug::prelude::*;
+
+use crate::ENVELOPE_CURVE_RANGE;
+
+/// ID of the persistent field holding the state version, must match the `#[persist]` key on
+/// `DrumSynthParams::state_version`
+pub(crate) const STATE_VERSION_KEY: &str = "state-version";
+
+/// Version of the state written by this build
+pub(crate) const STATE_VERSION: u32 = 4;
+
+/// `MIGRATIONS[n]` upgrades version `n` to version `n + 1`
+const MIGRATIONS: [fn(&mut PluginState); STATE_VERSION as usize] =
+    [migrate_v0_to_v1, migrate_v1_to_v2, migrate_v2_to_v3, migrate_v3_to_v4];
+
+/// Upgrade `state` to the current version in place
+pub(crate) fn migrate(state: &mut PluginState) {
+    let version = state_version(state);
+    if version > STATE_VERSION {
+        nih_warn!(
+            "State was saved by a newer version of the plugin (state version {}, this is {})",
+            version,
+            STATE_VERSION
+        );
+        return;
+    }
+
+    for (from, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        nih_log!("Migrating plugin state from version {} to {}", from, from + 1);
+        migration(state);
+    }
+
+    state
+        .fields
+        .insert(STATE_VERSION_KEY.to_owned(), STATE_VERSION.to_string());
+}
+
+/// States from before versioning was added don't have the field and count as version 0
+pub(crate) fn state_version(state: &PluginState) -> u32 {
+    state
+        .fields
+        .get(STATE_VERSION_KEY)
+        .and_then(|version| serde_json::from_str(version).ok())
+        .unwrap_or(0)
+}
+
+/// Version 0 covers every state from before versioning. The oldest of those come from before the
+/// master dynamics existed, so they don't have the limiter's switch and would otherwise get the
+/// limiter and its latency added. States that do have it keep their setting.
+fn migrate_v0_to_v1(state: &mut PluginState) {
+    if !state.params.contains_key("dyn_limiter") {
+        state.params.insert("dyn_limiter".to_owned(), ParamValue::Bool(false));
+    }
+}
+
+/// Version 2 added envelope curves and made the release the time to silence instead of a one-pole
+/// time constant. Older states keep their linear attack and decay, and the release is converted
+/// so it fades out the same way.
+fn migrate_v1_to_v2(state: &mut PluginState) {
+    for id in ["tr_attack_curve", "tr_decay_curve", "snare_attack_curve", "snare_decay_curve"] {
+        state.params.insert(id.to_owned(), ParamValue::F32(0.0));
+    }
+    state.params.insert("tr_release_curve".to_owned(), ParamValue::F32(1.0));
+    map_f32(state, "tr_release", |release| release * ENVELOPE_CURVE_RANGE);
+}
+
+/// Version 3 exposed the snare wires' hold, sustain and release, which used to be fixed at zero,
+/// zero and an exponential release with a time constant of half the decay
+fn migrate_v2_to_v3(state: &mut PluginState) {
+    let decay = match state.params.get("snare_decay") {
+        Some(ParamValue::F32(decay)) => *decay,
+        _ => 0.1,
+    };
+
+    for (id, value) in [
+        ("snare_hold", 0.0),
+        ("snare_sustain", 0.0),
+        ("snare_release", decay * 0.5 * ENVELOPE_CURVE_RANGE),
+        ("snare_release_curve", 1.0),
+        ("snare_delay", 0.0),
+    ] {
+        state.params.insert(id.to_owned(), ParamValue::F32(value));
+    }
+}
+
+/// Version 4 made hits follow the MIDI note velocity. Older states ignored it, so they keep
+/// playing every note at full level. The sequencer's step velocities don't depend on this.
+fn migrate_v3_to_v4(state: &mut PluginState) {
+    state.params.insert("vel_sens".to_owned(), ParamValue::F32(0.0));
+}
+
+/// Convert a float parameter's saved value, does nothing if the parameter wasn't saved
+pub(crate) fn map_f32(state: &mut PluginState, id: &str, f: impl FnOnce(f32) -> f32) {
+    if let Some(ParamValue::F32(value)) = state.params.get_mut(id) {
+        *value = f(*value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    /// A saved state with `params`, without a version field for `None`
+    fn state(version: Option<u32>, params: &[(&str, ParamValue)]) -> PluginState {
+        PluginState {
+            version: String::new(),
+            params: params
+                .iter()
+                .map(|(id, value)| (id.to_string(), value.clone()))
+                .collect(),
+            fields: version
+                .map(|version| (STATE_VERSION_KEY.to_owned(), version.to_string()))
+                .into_iter()
+                .collect::<BTreeMap<_, _>>(),
+        }
+    }
+
+    #[test]
+    fn unversioned_states_lose_the_limiter() {
+        let mut old = state(None, &[]);
+        migrate_v0_to_v1(&mut old);
+        assert_eq!(old.params.get("dyn_limiter"), Some(&ParamValue::Bool(false)));
+
+        // Saved after the dynamics were added, but before versioning
+        let mut newer = state(None, &[("dyn_limiter", ParamValue::Bool(true))]);
+        migrate_v0_to_v1(&mut newer);
+        assert_eq!(newer.params.get("dyn_limiter"), Some(&ParamValue::Bool(true)));
+    }
+
+    #[test]
+    fn migrating_brings_states_to_the_current_version() {
+        for version in [None, Some(1), Some(STATE_VERSION)] {
+            let mut old = state(version, &[]);
+            migrate(&mut old);
+            assert_eq!(state_version(&old), STATE_VERSION);
+        }
+    }
+
+    #[test]
+    fn current_states_are_left_alone() {
+        let params = [("vel_sens", ParamValue::F32(0.8)), ("tr_release", ParamValue::F32(0.3))];
+        let mut current = state(Some(STATE_VERSION), &params);
+        migrate(&mut current);
+        assert_eq!(current.params, state(None, &params).params);
+    }
+
+    #[test]
+    fn newer_states_are_left_alone() {
+        let mut newer = state(Some(STATE_VERSION + 1), &[("vel_sens", ParamValue::F32(0.8))]);
+        migrate(&mut newer);
+        assert_eq!(state_version(&newer), STATE_VERSION + 1);
+        assert_eq!(newer.params.get("vel_sens"), Some(&ParamValue::F32(0.8)));
+    }
+
+    #[test]
+    fn releases_become_times_to_silence() {
+        let mut old = state(Some(1), &[("tr_release", ParamValue::F32(0.2)), ("tr_decay_curve", ParamValue::F32(0.5))]);
+        migrate_v1_to_v2(&mut old);
+
+        assert_eq!(old.params.get("tr_release"), Some(&ParamValue::F32(0.2 * ENVELOPE_CURVE_RANGE)));
+        assert_eq!(old.params.get("tr_release_curve"), Some(&ParamValue::F32(1.0)));
+        for id in ["tr_attack_curve", "tr_decay_curve", "snare_attack_curve", "snare_decay_curve"] {
+            assert_eq!(old.params.get(id), Some(&ParamValue::F32(0.0)), "{id}");
+        }
+    }
+
+    #[test]
+    fn snare_wires_keep_their_fixed_envelope() {
+        let mut old = state(Some(2), &[("snare_decay", ParamValue::F32(0.3))]);
+        migrate_v2_to_v3(&mut old);
+
+        assert_eq!(old.params.get("snare_hold"), Some(&ParamValue::F32(0.0)));
+        assert_eq!(old.params.get("snare_sustain"), Some(&ParamValue::F32(0.0)));
+        assert_eq!(old.params.get("snare_release"), Some(&ParamValue::F32(0.3 * 0.5 * ENVELOPE_CURVE_RANGE)));
+        assert_eq!(old.params.get("snare_release_curve"), Some(&ParamValue::F32(1.0)));
+        assert_eq!(old.params.get("snare_delay"), Some(&ParamValue::F32(0.0)));
+    }
+
+    #[test]
+    fn midi_velocity_stays_ignored() {
+        let mut old = state(Some(3), &[]);
+        migrate_v3_to_v4(&mut old);
+        assert_eq!(old.params.get("vel_sens"), Some(&ParamValue::F32(0.0)));
+    }
+}
@@ -0,0 +1,509 @@
+use nih_plug::prelude::*;
+use std::f32::consts::TAU;
+
+use crate::noise::Pcg32;
+use crate::overrides::ParamOverrides;
+use crate::{ADSREnvelope, ADSRState, DrumSynthParams, LfoParams, ModEnvelopeParams, ModSlotParams};
+
+/// Number of LFOs and mod envelopes
+pub(crate) const MOD_SOURCES: usize = 2;
+
+/// Number of routings in the mod matrix
+pub(crate) const MOD_SLOTS: usize = 6;
+
+/// Seed for the sample & hold LFOs, they don't need to differ between instances
+const LFO_SEED: u64 = 0x1F0;
+
+#[derive(Enum, Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) enum LfoShape {
+    #[id = "sine"]
+    #[name = "Sine"]
+    Sine,
+    #[id = "triangle"]
+    #[name = "Triangle"]
+    Triangle,
+    #[id = "saw"]
+    #[name = "Saw"]
+    Saw,
+    #[id = "square"]
+    #[name = "Square"]
+    Square,
+    /// A new random value every cycle
+    #[id = "sample_hold"]
+    #[name = "Sample & Hold"]
+    SampleHold,
+}
+
+/// Length of one LFO cycle when synced to the host's tempo
+#[derive(Enum, Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) enum NoteDivision {
+    #[id = "4_1"]
+    #[name = "4 Bars"]
+    FourBars,
+    #[id = "2_1"]
+    #[name = "2 Bars"]
+    TwoBars,
+    #[id = "1_1"]
+    #[name = "1/1"]
+    Whole,
+    #[id = "1_2"]
+    #[name = "1/2"]
+    Half,
+    #[id = "1_4"]
+    #[name = "1/4"]
+    Quarter,
+    #[id = "1_8"]
+    #[name = "1/8"]
+    Eighth,
+    #[id = "1_8t"]
+    #[name = "1/8T"]
+    EighthTriplet,
+    #[id = "1_16"]
+    #[name = "1/16"]
+    Sixteenth,
+    #[id = "1_16t"]
+    #[name = "1/16T"]
+    SixteenthTriplet,
+    #[id = "1_32"]
+    #[name = "1/32"]
+    ThirtySecond,
+}
+
+impl NoteDivision {
+    /// Length in quarter notes
+    pub fn beats(self) -> f64 {
+        match self {
+            NoteDivision::FourBars => 16.0,
+            NoteDivision::TwoBars => 8.0,
+            NoteDivision::Whole => 4.0,
+            NoteDivision::Half => 2.0,
+            NoteDivision::Quarter => 1.0,
+            NoteDivision::Eighth => 0.5,
+            NoteDivision::EighthTriplet => 1.0 / 3.0,
+            NoteDivision::Sixteenth => 0.25,
+            NoteDivision::SixteenthTriplet => 1.0 / 6.0,
+            NoteDivision::ThirtySecond => 0.125,
+        }
+    }
+}
+
+#[derive(Enum, Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) enum ModSource {
+    #[id = "none"]
+    #[name = "None"]
+    None,
+    #[id = "lfo1"]
+    #[name = "LFO 1"]
+    Lfo1,
+    #[id = "lfo2"]
+    #[name = "LFO 2"]
+    Lfo2,
+    #[id = "env1"]
+    #[name = "Env 1"]
+    Envelope1,
+    #[id = "env2"]
+    #[name = "Env 2"]
+    Envelope2,
+}
+
+/// Parameters the mod matrix can modulate. These are all read once per sample, the EQs and the
+/// loop filter only recalculate their coefficients that often while a routing targets them.
+///
+/// This is a fixed subset of the layers' float parameters rather than all of them: the
+/// envelopes' times and curves, the pitch envelope and the noise are read once per hit, so a
+/// source moving them between hits would do nothing most of the time. Of the EQ bands only the
+/// frequencies are here, which is what sweeps move. New destinations go at the end so saved
+/// routings keep their targets.
+#[derive(Enum, Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) enum ModDestination {
+    #[id = "none"]
+    #[name = "None"]
+    None,
+    #[id = "tr_level"]
+    #[name = "Impact Level"]
+    ImpactLevel,
+    #[id = "tr_eq_freq"]
+    #[name = "Impact Tone"]
+    ImpactTone,
+    #[id = "tr_eq2_freq"]
+    #[name = "Impact Tone 2"]
+    ImpactTone2,
+    #[id = "tr_eq_lc_freq"]
+    #[name = "Impact Low Cut"]
+    ImpactLowCut,
+    #[id = "tr_eq_hc_freq"]
+    #[name = "Impact High Cut"]
+    ImpactHighCut,
+    #[id = "res_delay_samples"]
+    #[name = "Tension"]
+    Tension,
+    #[id = "res_feedback"]
+    #[name = "Sustain"]
+    Sustain,
+    #[id = "res_damping"]
+    #[name = "Damping"]
+    Damping,
+    #[id = "res_stiffness"]
+    #[name = "Stiffness"]
+    Stiffness,
+    #[id = "res_level"]
+    #[name = "Tuning Level"]
+    TuningLevel,
+    #[id = "res_eq_freq"]
+    #[name = "Tuning Tone"]
+    TuningTone,
+    #[id = "res_eq2_freq"]
+    #[name = "Tuning Tone 2"]
+    TuningTone2,
+    #[id = "res_eq_lc_freq"]
+    #[name = "Tuning Low Cut"]
+    TuningLowCut,
+    #[id = "res_eq_hc_freq"]
+    #[name = "Tuning High Cut"]
+    TuningHighCut,
+    #[id = "snare_level"]
+    #[name = "Snare Level"]
+    SnareLevel,
+    #[id = "snare_eq_freq"]
+    #[name = "Snare Tone"]
+    SnareTone,
+    #[id = "snare_eq2_freq"]
+    #[name = "Snare Tone 2"]
+    SnareTone2,
+    #[id = "snare_eq_lc_freq"]
+    #[name = "Snare Low Cut"]
+    SnareLowCut,
+    #[id = "snare_eq_hc_freq"]
+    #[name = "Snare High Cut"]
+    SnareHighCut,
+}
+
+impl ModDestination {
+    /// Whether the destination is one of the EQs' or the loop filter's parameters. Without
+    /// modulation those follow their smoothers at control rate.
+    pub fn is_filter(self) -> bool {
+        !matches!(
+            self,
+            ModDestination::None
+                | ModDestination::ImpactLevel
+                | ModDestination::Tension
+                | ModDestination::Sustain
+                | ModDestination::TuningLevel
+                | ModDestination::SnareLevel
+        )
+    }
+
+    pub fn param(self, params: &DrumSynthParams) -> Option<&FloatParam> {
+        let impact = &params.impact_params;
+        let tuning = &params.tuning_params;
+        let snare = &params.snare_params;
+
+        Some(match self {
+            ModDestination::None => return None,
+            ModDestination::ImpactLevel => &impact.level,
+            ModDestination::ImpactTone => &impact.eq.peak_freq,
+            ModDestination::ImpactTone2 => &impact.eq.peak2_freq,
+            ModDestination::ImpactLowCut => &impact.eq.low_cut_freq,
+            ModDestination::ImpactHighCut => &impact.eq.high_cut_freq,
+            ModDestination::Tension => &tuning.delay_samples,
+            ModDestination::Sustain => &tuning.feedback,
+            ModDestination::Damping => &tuning.damping,
+            ModDestination::Stiffness => &tuning.stiffness,
+            ModDestination::TuningLevel => &tuning.level,
+            ModDestination::TuningTone => &tuning.eq.peak_freq,
+            ModDestination::TuningTone2 => &tuning.eq.peak2_freq,
+            ModDestination::TuningLowCut => &tuning.eq.low_cut_freq,
+            ModDestination::TuningHighCut => &tuning.eq.high_cut_freq,
+            ModDestination::SnareLevel => &snare.level,
+            ModDestination::SnareTone => &snare.eq.peak_freq,
+            ModDestination::SnareTone2 => &snare.eq.peak2_freq,
+            ModDestination::SnareLowCut => &snare.eq.low_cut_freq,
+            ModDestination::SnareHighCut => &snare.eq.high_cut_freq,
+        })
+    }
+}
+
+/// The host's transport at the start of a block, used for tempo synced LFOs
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct TransportInfo {
+    pub tempo: Option<f64>,
+    /// Song position in quarter notes, only set while the transport is playing
+    pub pos_beats: Option<f64>,
+}
+
+struct Lfo {
+    phase: f32,
+    phase_increment: f32,
+    shape: LfoShape,
+    retrigger: bool,
+    held_value: f32,
+    rng: Pcg32,
+}
+
+impl Lfo {
+    fn new(seed: u64) -> Self {
+        Self {
+            phase: 0.0,
+            phase_increment: 0.0,
+            shape: LfoShape::Sine,
+            retrigger: false,
+            held_value: 0.0,
+            rng: Pcg32::new(seed),
+        }
+    }
+
+    fn configure(&mut self, params: &LfoParams, overrides: &ParamOverrides, transport: TransportInfo, sample_rate: f32) {
+        self.shape = overrides.enumeration(&params.shape);
+        self.retrigger = overrides.bool(&params.retrigger);
+
+        let tempo = transport.tempo.filter(|_| overrides.bool(&params.sync));
+        match tempo {
+            Some(tempo) => {
+                let beats = overrides.enumeration(&params.division).beats();
+                self.phase_increment = (tempo / 60.0 / beats) as f32 / sample_rate;
+
+                // Follow the song position so the LFO lines up with the beat
+                if let Some(pos_beats) = transport.pos_beats {
+                    if !self.retrigger {
+                        self.phase = (pos_beats / beats).fract() as f32;
+                    }
+                }
+            }
+            None => self.phase_increment = overrides.float(&params.rate) / sample_rate,
+        }
+    }
+
+    fn trigger(&mut self) {
+        if self.retrigger {
+            self.phase = 0.0;
+        }
+    }
+
+    /// Advance the phase by `samples` samples without producing any output
+    fn skip(&mut self, samples: u32) {
+        self.phase += self.phase_increment * samples as f32;
+        if self.phase >= 1.0 {
+            self.phase = self.phase.fract();
+            self.held_value = self.rng.next_bipolar();
+        }
+    }
+
+    /// Bipolar output in [-1.0, 1.0]
+    fn next_sample(&mut self) -> f32 {
+        let phase = self.phase;
+        self.phase += self.phase_increment;
+        if self.phase >= 1.0 {
+            self.phase -= 1.0;
+            self.held_value = self.rng.next_bipolar();
+        }
+
+        match self.shape {
+            LfoShape::Sine => (TAU * phase).sin(),
+            LfoShape::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+            LfoShape::Saw => 2.0 * phase - 1.0,
+            LfoShape::Square => {
+                if phase < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            LfoShape::SampleHold => self.held_value,
+        }
+    }
+}
+
+/// Next value of a mod envelope. They have no sustain, so they're done once the decay has run
+/// out instead of staying active at zero until a release that never comes.
+fn envelope_sample(envelope: &mut ADSREnvelope) -> f32 {
+    let value = envelope.process();
+    if envelope.state == ADSRState::Sustain {
+        envelope.state = ADSRState::Idle;
+    }
+
+    value
+}
+
+/// Routes the LFOs and mod envelopes to the layer parameters. Sources are evaluated once per
+/// output sample and the result is added to the destinations' normalized values. The filters
+/// are normally updated at control rate, see `FILTER_UPDATE_INTERVAL`, but follow every sample
+/// while `modulates_filters()` is set so sweeps don't step.
+pub(crate) struct ModMatrix {
+    lfos: [Lfo; MOD_SOURCES],
+    envelopes: [ADSREnvelope; MOD_SOURCES],
+
+    /// `(address of the FloatParam, destination index)`, sorted by address for lookups
+    targets: Vec<(usize, usize)>,
+    /// Normalized offset for every destination, indexed like `ModDestination`
+    offsets: Vec<f32>,
+    routings: [(ModSource, ModDestination, f32); MOD_SLOTS],
+    /// Whether any routing targets an EQ or the loop filter
+    modulates_filters: bool,
+}
+
+impl ModMatrix {
+    pub fn new(params: &DrumSynthParams) -> Self {
+        let destinations = ModDestination::variants().len();
+        let mut targets: Vec<(usize, usize)> = (0..destinations)
+            .filter_map(|index| {
+                ModDestination::from_index(index)
+                    .param(params)
+                    .map(|param| (param as *const FloatParam as usize, index))
+            })
+            .collect();
+        targets.sort_unstable();
+
+        Self {
+            lfos: std::array::from_fn(|i| Lfo::new(LFO_SEED + i as u64)),
+            envelopes: std::array::from_fn(|_| ADSREnvelope::new(44100.0)),
+            targets,
+            offsets: vec![0.0; destinations],
+            routings: [(ModSource::None, ModDestination::None, 0.0); MOD_SLOTS],
+            modulates_filters: false,
+        }
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        for envelope in &mut self.envelopes {
+            envelope.sample_rate = sample_rate;
+        }
+    }
+
+    /// Read the sources' and routings' parameters, called once per block
+    pub fn configure(
+        &mut self,
+        lfos: &[LfoParams; MOD_SOURCES],
+        envelopes: &[ModEnvelopeParams; MOD_SOURCES],
+        slots: &[ModSlotParams; MOD_SLOTS],
+        overrides: &ParamOverrides,
+        transport: TransportInfo,
+        sample_rate: f32,
+    ) {
+        for (lfo, params) in self.lfos.iter_mut().zip(lfos) {
+            lfo.configure(params, overrides, transport, sample_rate);
+        }
+
+        for (envelope, params) in self.envelopes.iter_mut().zip(envelopes) {
+            envelope.set_parameters(
+                overrides.float(&params.attack),
+                overrides.float(&params.decay),
+                0.0,
+                overrides.float(&params.decay),
+                overrides.float(&params.hold),
+            );
+        }
+
+        for (routing, slot) in self.routings.iter_mut().zip(slots) {
+            *routing = (
+                overrides.enumeration(&slot.source),
+                overrides.enumeration(&slot.destination),
+                overrides.float(&slot.depth),
+            );
+        }
+        self.modulates_filters = self.routings.iter().any(|&(source, destination, depth)| {
+            source != ModSource::None && destination.is_filter() && depth != 0.0
+        });
+    }
+
+    pub fn modulates_filters(&self) -> bool {
+        self.modulates_filters
+    }
+
+    /// Restart the envelopes and retriggered LFOs for a new hit
+    pub fn trigger(&mut self) {
+        for lfo in &mut self.lfos {
+            lfo.trigger();
+        }
+        for envelope in &mut self.envelopes {
+            envelope.note_on();
+        }
+    }
+
+    pub fn reset(&mut self) {
+        for envelope in &mut self.envelopes {
+            envelope.state = ADSRState::Idle;
+        }
+        self.offsets.fill(0.0);
+    }
+
+    /// Advance every source by one sample and sum the routings per destination
+    pub fn next_sample(&mut self) {
+        let lfos: [f32; MOD_SOURCES] = std::array::from_fn(|i| self.lfos[i].next_sample());
+        let envelopes: [f32; MOD_SOURCES] = std::array::from_fn(|i| envelope_sample(&mut self.envelopes[i]));
+
+        self.offsets.fill(0.0);
+        for (source, destination, depth) in self.routings {
+            let value = match source {
+                ModSource::None => continue,
+                ModSource::Lfo1 => lfos[0],
+                ModSource::Lfo2 => lfos[1],
+                ModSource::Envelope1 => envelopes[0],
+                ModSource::Envelope2 => envelopes[1],
+            };
+            self.offsets[destination.to_index()] += value * depth;
+        }
+    }
+
+    /// Advance every source by `samples` samples without summing the routings, for stretches
+    /// where nothing is synthesized
+    pub fn skip(&mut self, samples: u32) {
+        for lfo in &mut self.lfos {
+            lfo.skip(samples);
+        }
+        for envelope in &mut self.envelopes {
+            for _ in 0..samples {
+                if !envelope.is_active() {
+                    break;
+                }
+                envelope_sample(envelope);
+            }
+        }
+    }
+
+    /// Apply the modulation for `param` to its current plain value
+    pub fn apply(&self, param: &FloatParam, value: f32) -> f32 {
+        let address = param as *const FloatParam as usize;
+        let Ok(index) = self.targets.binary_search_by_key(&address, |(target, _)| *target) else {
+            return value;
+        };
+
+        let offset = self.offsets[self.targets[index].1];
+        if offset == 0.0 {
+            return value;
+        }
+
+        param.preview_plain((param.preview_normalized(value) + offset).clamp(0.0, 1.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn envelopes_finish_after_their_decay() {
+        let params = DrumSynthParams::default();
+        let overrides = ParamOverrides::new(&params);
+        let transport = TransportInfo {
+            tempo: None,
+            pos_beats: None,
+        };
+
+        let mut matrix = ModMatrix::new(&params);
+        matrix.set_sample_rate(48000.0);
+        matrix.configure(
+            &params.lfo_params,
+            &params.mod_envelope_params,
+            &params.mod_slot_params,
+            &overrides,
+            transport,
+            48000.0,
+        );
+        matrix.trigger();
+        assert!(matrix.envelopes.iter().all(ADSREnvelope::is_active));
+
+        // The longest attack, hold and decay add up to 3.5 seconds
+        matrix.skip(4 * 48000);
+        assert!(!matrix.envelopes.iter().any(ADSREnvelope::is_active));
+    }
+}
@@ -0,0 +1,236 @@
+use nih_plug::prelude::*;
+use std::collections::BTreeMap;
+use std::sync::atomic::Ordering;
+
+use crate::overrides::ParamOverrides;
+use crate::{DrumSynthParams, MorphParams};
+
+/// Parameter groups of the three sound layers, their continuous parameters are morphed
+pub(crate) const LAYER_GROUPS: [&str; 3] = ["Impact", "Tuning", "Snare"];
+
+/// Plain values of the morphed parameters, keyed by parameter ID so snapshots survive range
+/// changes the same way the rest of the state does
+pub(crate) type MorphSnapshot = BTreeMap<String, f32>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum MorphSlot {
+    A,
+    B,
+}
+
+/// The float parameters in the layer groups as `(id, param_ptr, group)`
+pub(crate) fn layer_float_params(params: &DrumSynthParams) -> impl Iterator<Item = (String, ParamPtr, String)> {
+    params.param_map().into_iter().filter(|(_, param_ptr, group)| {
+        matches!(param_ptr, ParamPtr::FloatParam(_))
+            && LAYER_GROUPS.iter().any(|layer_group| group.starts_with(layer_group))
+    })
+}
+
+/// Store the current values of the morphable parameters in a snapshot slot
+pub(crate) fn store_snapshot(params: &DrumSynthParams, slot: MorphSlot) {
+    let snapshot: MorphSnapshot = layer_float_params(params)
+        // SAFETY: The pointers come straight from the params object we're holding a reference to
+        .map(|(id, param_ptr, _)| (id, unsafe { param_ptr.unmodulated_plain_value() }))
+        .collect();
+
+    let morph_params = &params.morph_params;
+    let slot = match slot {
+        MorphSlot::A => &morph_params.snapshot_a,
+        MorphSlot::B => &morph_params.snapshot_b,
+    };
+    *slot.write().unwrap() = Some(snapshot);
+    morph_params.snapshot_generation.fetch_add(1, Ordering::Release);
+}
+
+/// Write an edited parameter's new value into the snapshot the morph is closer to, so the knobs
+/// keep working while both snapshots are filled. Does nothing for parameters that aren't morphed.
+pub(crate) fn capture_edit(params: &DrumSynthParams, param_ptr: ParamPtr) {
+    let Some((id, _, _)) = layer_float_params(params).find(|(_, layer_param, _)| *layer_param == param_ptr) else {
+        return;
+    };
+
+    let morph_params = &params.morph_params;
+    let (Ok(mut snapshot_a), Ok(mut snapshot_b)) =
+        (morph_params.snapshot_a.write(), morph_params.snapshot_b.write())
+    else {
+        return;
+    };
+    let (Some(snapshot_a), Some(snapshot_b)) = (snapshot_a.as_mut(), snapshot_b.as_mut()) else {
+        return;
+    };
+
+    let snapshot = if morph_params.amount.unmodulated_plain_value() < 50.0 { snapshot_a } else { snapshot_b };
+    // SAFETY: The pointer comes straight from the params object we're holding a reference to
+    snapshot.insert(id, unsafe { param_ptr.unmodulated_plain_value() });
+    morph_params.snapshot_generation.fetch_add(1, Ordering::Release);
+}
+
+/// Empty both snapshot slots, which turns morphing off
+pub(crate) fn clear_snapshots(params: &DrumSynthParams) {
+    let morph_params = &params.morph_params;
+    *morph_params.snapshot_a.write().unwrap() = None;
+    *morph_params.snapshot_b.write().unwrap() = None;
+    morph_params.snapshot_generation.fetch_add(1, Ordering::Release);
+}
+
+struct MorphTarget {
+    /// Address of the `FloatParam`, targets are sorted by this for lookups
+    address: usize,
+    id: String,
+    param_ptr: ParamPtr,
+    /// Normalized values in snapshots A and B
+    a: f32,
+    b: f32,
+}
+
+/// Audio thread side of the A/B morph. While both snapshots are filled, every morphable
+/// parameter follows the snapshots instead of its own value, except at 0% where the parameters
+/// play their own values. Edits are captured into snapshot A there, so the two agree.
+pub(crate) struct Morph {
+    targets: Vec<MorphTarget>,
+    active: bool,
+    amount: f32,
+    generation: u32,
+}
+
+impl Morph {
+    pub fn new(params: &DrumSynthParams) -> Self {
+        let mut targets: Vec<MorphTarget> = layer_float_params(params)
+            .map(|(id, param_ptr, _)| MorphTarget {
+                address: match param_ptr {
+                    ParamPtr::FloatParam(param) => param as usize,
+                    _ => unreachable!(),
+                },
+                id,
+                param_ptr,
+                a: 0.0,
+                b: 0.0,
+            })
+            .collect();
+        targets.sort_by_key(|target| target.address);
+
+        Self {
+            targets,
+            active: false,
+            amount: 0.0,
+            generation: 0,
+        }
+    }
+
+    /// Pick up new snapshots if the editor changed them, called once per block
+    pub fn update(&mut self, params: &MorphParams) {
+        if params.snapshot_generation.load(Ordering::Acquire) != self.generation {
+            self.refresh(params);
+        }
+    }
+
+    /// Reload both snapshots. Doesn't block, if the editor is writing a snapshot right now this
+    /// gets retried on the next block.
+    pub fn refresh(&mut self, params: &MorphParams) {
+        let generation = params.snapshot_generation.load(Ordering::Acquire);
+        let (Ok(snapshot_a), Ok(snapshot_b)) = (params.snapshot_a.try_read(), params.snapshot_b.try_read()) else {
+            return;
+        };
+
+        self.active = false;
+        if let (Some(snapshot_a), Some(snapshot_b)) = (snapshot_a.as_ref(), snapshot_b.as_ref()) {
+            for target in &mut self.targets {
+                // SAFETY: The targets point into the params object that outlives the plugin
+                unsafe {
+                    let default = target.param_ptr.default_plain_value();
+                    let a = snapshot_a.get(&target.id).copied().unwrap_or(default);
+                    let b = snapshot_b.get(&target.id).copied().unwrap_or(default);
+                    target.a = target.param_ptr.preview_normalized(a);
+                    target.b = target.param_ptr.preview_normalized(b);
+                }
+            }
+            self.active = true;
+        }
+
+        self.generation = generation;
+    }
+
+    /// Set the position between snapshot A (0.0) and B (1.0)
+    pub fn set_amount(&mut self, amount: f32) {
+        self.amount = amount;
+    }
+
+    fn morphed(&self, param: &FloatParam) -> Option<f32> {
+        if !self.active || self.amount <= 0.0 {
+            return None;
+        }
+
+        let address = param as *const FloatParam as usize;
+        let index = self.targets.binary_search_by_key(&address, |target| target.address).ok()?;
+        let target = &self.targets[index];
+
+        // Interpolating the normalized values follows the parameters' own skew
+        let normalized = if self.amount >= 1.0 {
+            target.b
+        } else {
+            target.a + (target.b - target.a) * self.amount
+        };
+        // SAFETY: See above
+        Some(unsafe { target.param_ptr.preview_plain(normalized) })
+    }
+
+    /// The parameter's value, or its morphed value while morphing
+    pub fn value(&self, param: &FloatParam, overrides: &ParamOverrides) -> f32 {
+        self.morphed(param).unwrap_or_else(|| overrides.float(param))
+    }
+
+    /// Like `value()`, but advances the parameter's smoother by `steps` samples. While morphing
+    /// the morph amount's smoothing takes over.
+    pub fn smoothed(&self, param: &FloatParam, overrides: &ParamOverrides, steps: u32) -> f32 {
+        let value = overrides.smoothed_steps(param, steps);
+        self.morphed(param).unwrap_or(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Snapshots with the impact level at `a` and `b`, normalized
+    fn snapshots(params: &DrumSynthParams, a: f32, b: f32) {
+        let level = &params.impact_params.level;
+        let snapshot = |normalized| MorphSnapshot::from([("tr_level".to_owned(), level.preview_plain(normalized))]);
+        *params.morph_params.snapshot_a.write().unwrap() = Some(snapshot(a));
+        *params.morph_params.snapshot_b.write().unwrap() = Some(snapshot(b));
+    }
+
+    #[test]
+    fn morph_ends_are_exact() {
+        let params = DrumSynthParams::default();
+        let overrides = ParamOverrides::new(&params);
+        let level = &params.impact_params.level;
+        snapshots(&params, 0.3, 0.7);
+
+        let mut morph = Morph::new(&params);
+        morph.refresh(&params.morph_params);
+        let mut morphed = |amount| {
+            morph.set_amount(amount);
+            morph.value(level, &overrides)
+        };
+        let snapshot_b = params.morph_params.snapshot_b.read().unwrap().as_ref().unwrap()["tr_level"];
+
+        // The knob's own value at 0%, not snapshot A
+        assert_eq!(morphed(0.0), level.value());
+        assert!((morphed(1.0) - snapshot_b).abs() <= 1e-6 * snapshot_b.abs());
+        assert!((level.preview_normalized(morphed(0.5)) - 0.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn edits_are_captured_into_the_closer_snapshot() {
+        let params = DrumSynthParams::default();
+        let level = &params.impact_params.level;
+        snapshots(&params, 0.3, 0.7);
+
+        // The morph is at 0% by default
+        capture_edit(&params, level.as_ptr());
+        let snapshot_a = params.morph_params.snapshot_a.read().unwrap();
+        assert_eq!(snapshot_a.as_ref().unwrap()["tr_level"], level.value());
+        let snapshot_b = params.morph_params.snapshot_b.read().unwrap();
+        assert_eq!(snapshot_b.as_ref().unwrap()["tr_level"], level.preview_plain(0.7));
+    }
+}
@@ -0,0 +1,270 @@
+use nih_plug::prelude::Enum;
+
+/// Average number of impulses per second in velvet noise
+const VELVET_DENSITY: f32 = 2000.0;
+
+/// Rate the pink and brown noise filters were designed for, their poles are moved to keep the
+/// same corner frequencies at other rates
+const FILTER_DESIGN_RATE: f32 = 44100.0;
+
+/// Poles and input weights of Paul Kellet's pink noise filter sections at `FILTER_DESIGN_RATE`
+const PINK_POLES: [f32; 5] = [0.99886, 0.99332, 0.96900, 0.86650, 0.55000];
+const PINK_WEIGHTS: [f32; 5] = [0.0555179, 0.0750759, 0.1538520, 0.3104856, 0.5329522];
+
+/// Pole of the leaky integrator for brown noise at `FILTER_DESIGN_RATE`
+const BROWN_POLE: f32 = 1.0 / 1.02;
+
+/// Spectrum of a layer's noise source
+#[derive(Enum, Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) enum NoiseColor {
+    #[id = "white"]
+    #[name = "White"]
+    White,
+    /// -3 dB/octave
+    #[id = "pink"]
+    #[name = "Pink"]
+    Pink,
+    /// -6 dB/octave
+    #[id = "brown"]
+    #[name = "Brown"]
+    Brown,
+    /// Sparse random impulses, sounds smoother than white noise at the same density
+    #[id = "velvet"]
+    #[name = "Velvet"]
+    Velvet,
+}
+
+/// How the noise generators are seeded on every hit
+#[derive(Enum, Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) enum NoiseSeedMode {
+    #[id = "random"]
+    #[name = "Random Per Hit"]
+    RandomPerHit,
+    /// Every hit uses the same noise, so renders are reproducible
+    #[id = "fixed"]
+    #[name = "Fixed Per Hit"]
+    FixedPerHit,
+}
+
+/// PCG32 (XSH RR) random number generator, small and fast enough for the audio thread
+pub(crate) struct Pcg32 {
+    state: u64,
+    increment: u64,
+}
+
+impl Pcg32 {
+    const MULTIPLIER: u64 = 6364136223846793005;
+
+    /// PCG's default stream
+    const DEFAULT_STREAM: u64 = 721347520444481703;
+
+    pub fn new(seed: u64) -> Self {
+        Self::with_stream(seed, Self::DEFAULT_STREAM)
+    }
+
+    /// A generator on one of PCG's independent sequences, like `pcg32_srandom(seed, stream)`
+    fn with_stream(seed: u64, stream: u64) -> Self {
+        let mut rng = Self {
+            state: 0,
+            increment: (stream << 1) | 1,
+        };
+        rng.seed(seed);
+        rng
+    }
+
+    /// Restart the sequence, the same seed always produces the same sequence
+    pub fn seed(&mut self, seed: u64) {
+        self.state = 0;
+        self.next_u32();
+        self.state = self.state.wrapping_add(seed);
+        self.next_u32();
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let old_state = self.state;
+        self.state = old_state
+            .wrapping_mul(Self::MULTIPLIER)
+            .wrapping_add(self.increment);
+
+        let xor_shifted = (((old_state >> 18) ^ old_state) >> 27) as u32;
+        let rotation = (old_state >> 59) as u32;
+        xor_shifted.rotate_right(rotation)
+    }
+
+    /// Uniform value in [0.0, 1.0)
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 / (1u32 << 24) as f32
+    }
+
+    /// Uniform value in [-1.0, 1.0)
+    pub fn next_bipolar(&mut self) -> f32 {
+        self.next_f32() * 2.0 - 1.0
+    }
+}
+
+/// Colored noise source driven by its own `Pcg32`
+pub(crate) struct NoiseGenerator {
+    rng: Pcg32,
+    color: NoiseColor,
+    sample_rate: f32,
+    /// Makes up for the noise power that lies above the host's Nyquist frequency when
+    /// oversampling, which the decimation filters remove
+    gain: f32,
+
+    /// Paul Kellet's pink noise filter state, and the coefficients of its first sections for
+    /// the current rate
+    pink: [f32; 7],
+    pink_poles: [f32; 5],
+    pink_weights: [f32; 5],
+    brown: f32,
+    brown_pole: f32,
+    brown_weight: f32,
+
+    /// Position inside the current velvet noise period and where its impulse goes
+    velvet_pos: usize,
+    velvet_impulse_pos: usize,
+    velvet_sign: f32,
+}
+
+impl NoiseGenerator {
+    pub fn new(seed: u64) -> Self {
+        let mut generator = Self {
+            rng: Pcg32::new(seed),
+            color: NoiseColor::White,
+            sample_rate: FILTER_DESIGN_RATE,
+            gain: 1.0,
+            pink: [0.0; 7],
+            pink_poles: PINK_POLES,
+            pink_weights: PINK_WEIGHTS,
+            brown: 0.0,
+            brown_pole: BROWN_POLE,
+            brown_weight: 1.0 - BROWN_POLE,
+            velvet_pos: 0,
+            velvet_impulse_pos: 0,
+            velvet_sign: 1.0,
+        };
+        generator.set_sample_rate(FILTER_DESIGN_RATE, 1);
+        generator
+    }
+
+    /// `sample_rate` is the rate the noise is generated at, `oversampling` times the host's rate
+    pub fn set_sample_rate(&mut self, sample_rate: f32, oversampling: usize) {
+        self.sample_rate = sample_rate;
+        // The power is spread evenly over the whole band, only a 1/oversampling part of it stays
+        self.gain = (oversampling as f32).sqrt();
+
+        // Keep the filters' corners in Hz and their gain at DC, so the colors have the same
+        // spectrum at every rate
+        let (pole, weight) = Self::scale_section(BROWN_POLE, 1.0 - BROWN_POLE, sample_rate);
+        self.brown_pole = pole;
+        self.brown_weight = weight;
+        for (i, (&pole, &weight)) in PINK_POLES.iter().zip(&PINK_WEIGHTS).enumerate() {
+            (self.pink_poles[i], self.pink_weights[i]) = Self::scale_section(pole, weight, sample_rate);
+        }
+    }
+
+    /// Pole and input weight of a one pole lowpass section designed at `FILTER_DESIGN_RATE`,
+    /// moved to `sample_rate`
+    fn scale_section(pole: f32, weight: f32, sample_rate: f32) -> (f32, f32) {
+        let scaled_pole = pole.powf(FILTER_DESIGN_RATE / sample_rate);
+        (scaled_pole, weight * (1.0 - scaled_pole) / (1.0 - pole))
+    }
+
+    pub fn set_color(&mut self, color: NoiseColor) {
+        self.color = color;
+    }
+
+    /// Restart the noise from a seed and clear the coloring filters
+    pub fn seed(&mut self, seed: u64) {
+        self.rng.seed(seed);
+        self.pink = [0.0; 7];
+        self.brown = 0.0;
+        self.velvet_pos = 0;
+        self.velvet_impulse_pos = 0;
+    }
+
+    fn velvet_period(&self) -> usize {
+        ((self.sample_rate / VELVET_DENSITY) as usize).max(1)
+    }
+
+    /// Generate one sample, roughly in [-1.0, 1.0] for every color before the oversampling gain
+    pub fn next_sample(&mut self) -> f32 {
+        self.gain * self.next_unscaled()
+    }
+
+    fn next_unscaled(&mut self) -> f32 {
+        match self.color {
+            NoiseColor::White => self.rng.next_bipolar(),
+            NoiseColor::Pink => {
+                let white = self.rng.next_bipolar();
+                let b = &mut self.pink;
+                for ((state, pole), weight) in b.iter_mut().zip(&self.pink_poles).zip(&self.pink_weights) {
+                    *state = pole * *state + white * weight;
+                }
+                // Evens out the top octave of the generator's band, which doesn't depend on the rate
+                b[5] = -0.7616 * b[5] - white * 0.0168980;
+                let pink = b[0] + b[1] + b[2] + b[3] + b[4] + b[5] + b[6] + white * 0.5362;
+                b[6] = white * 0.115926;
+                pink * 0.11
+            }
+            NoiseColor::Brown => {
+                let white = self.rng.next_bipolar();
+                self.brown = self.brown_pole * self.brown + self.brown_weight * white;
+                self.brown * 3.5
+            }
+            NoiseColor::Velvet => {
+                let period = self.velvet_period();
+                if self.velvet_pos == 0 {
+                    self.velvet_impulse_pos = (self.rng.next_f32() * period as f32) as usize;
+                    self.velvet_sign = if self.rng.next_u32() & 1 == 0 { 1.0 } else { -1.0 };
+                }
+
+                let output = if self.velvet_pos == self.velvet_impulse_pos { self.velvet_sign } else { 0.0 };
+                self.velvet_pos = (self.velvet_pos + 1) % period;
+                output
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// First outputs of the reference implementation's demo, `pcg32_srandom(42, 54)`
+    #[test]
+    fn pcg32_matches_reference() {
+        let mut rng = Pcg32::with_stream(42, 54);
+        let expected = [0xa15c02b7, 0x7b47f409, 0xba1d3330, 0x83d2f293, 0xbfa4784b, 0xcbed606e];
+        for value in expected {
+            assert_eq!(rng.next_u32(), value);
+        }
+    }
+
+    #[test]
+    fn same_seed_same_noise() {
+        for color in [NoiseColor::White, NoiseColor::Pink, NoiseColor::Brown, NoiseColor::Velvet] {
+            let mut first = NoiseGenerator::new(7);
+            let mut second = NoiseGenerator::new(1234);
+            for generator in [&mut first, &mut second] {
+                generator.set_color(color);
+                generator.set_sample_rate(96000.0, 2);
+                generator.next_sample();
+            }
+
+            // Reseeding restarts both from the same point, whatever they played before
+            first.seed(99);
+            second.seed(99);
+            for _ in 0..1000 {
+                assert_eq!(first.next_sample(), second.next_sample(), "{color:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn different_seeds_differ() {
+        let mut first = Pcg32::new(1);
+        let mut second = Pcg32::new(2);
+        assert!((0..16).any(|_| first.next_u32() != second.next_u32()));
+    }
+}
@@ -0,0 +1,113 @@
+use nih_plug::prelude::*;
+
+use crate::presets::Preset;
+use crate::DrumSynthParams;
+
+/// The parameter's address, which identifies it without its ID. Enum parameters are identified
+/// by their inner parameter.
+fn address(param_ptr: ParamPtr) -> usize {
+    match param_ptr {
+        ParamPtr::FloatParam(param) => param as usize,
+        ParamPtr::IntParam(param) => param as usize,
+        ParamPtr::BoolParam(param) => param as usize,
+        ParamPtr::EnumParam(param) => param as usize,
+    }
+}
+
+/// The plain value of every parameter in `param_map()` order, which is the same for all
+/// `DrumSynthParams` objects. Allocates.
+pub(crate) fn plain_values(params: &DrumSynthParams, preset: &Preset) -> Vec<f32> {
+    let mut values = Vec::new();
+    preset.apply(params, |param_ptr, normalized| {
+        // SAFETY: The pointers come straight from the params object we're holding a reference to
+        values.push(unsafe { param_ptr.preview_plain(normalized) });
+    });
+
+    values
+}
+
+/// Plain values that replace the parameters' own values. nih_plug only lets the host change
+/// parameters, so this is how a preset gets played without one, for example when rendering
+/// offline. Without any overrides every parameter reads its own value.
+pub(crate) struct ParamOverrides {
+    /// `(address, index into values)`, sorted by address for lookups
+    indices: Vec<(usize, usize)>,
+    /// Plain values in `param_map()` order, empty without overrides. Has room for every
+    /// parameter so switching patches doesn't allocate.
+    values: Vec<f32>,
+}
+
+impl ParamOverrides {
+    /// No overrides yet for the parameters in `params`
+    pub fn new(params: &DrumSynthParams) -> Self {
+        let mut indices: Vec<_> = params
+            .param_map()
+            .into_iter()
+            .enumerate()
+            .map(|(index, (_, param_ptr, _))| (address(param_ptr), index))
+            .collect();
+        indices.sort_by_key(|(address, _)| *address);
+
+        Self {
+            values: Vec::with_capacity(indices.len()),
+            indices,
+        }
+    }
+
+    /// Override every parameter with the preset's value. Allocates, so this can't be called
+    /// from the audio thread.
+    pub fn load(&mut self, params: &DrumSynthParams, preset: &Preset) {
+        self.set(&plain_values(params, preset));
+    }
+
+    /// Override every parameter with `values` from `plain_values()`, without allocating
+    pub fn set(&mut self, values: &[f32]) {
+        debug_assert_eq!(values.len(), self.indices.len());
+
+        self.values.clear();
+        self.values.extend_from_slice(values);
+    }
+
+    fn lookup(&self, param_ptr: ParamPtr) -> Option<f32> {
+        if self.values.is_empty() {
+            return None;
+        }
+
+        let address = address(param_ptr);
+        let position = self.indices.binary_search_by_key(&address, |(key, _)| *key).ok()?;
+        Some(self.values[self.indices[position].1])
+    }
+
+    pub fn float(&self, param: &FloatParam) -> f32 {
+        self.lookup(param.as_ptr()).unwrap_or_else(|| param.value())
+    }
+
+    /// Like `float()`, but advances the parameter's smoother. Overridden values are constant so
+    /// they don't need smoothing.
+    pub fn smoothed(&self, param: &FloatParam) -> f32 {
+        self.smoothed_steps(param, 1)
+    }
+
+    /// Like `smoothed()`, but advances the smoother by `steps` samples at once. With zero steps
+    /// this reads the smoother's current value without advancing it.
+    pub fn smoothed_steps(&self, param: &FloatParam, steps: u32) -> f32 {
+        let value = match steps {
+            0 => param.smoothed.previous_value(),
+            steps => param.smoothed.next_step(steps),
+        };
+        self.lookup(param.as_ptr()).unwrap_or(value)
+    }
+
+    pub fn int(&self, param: &IntParam) -> i32 {
+        self.lookup(param.as_ptr()).map_or_else(|| param.value(), |plain| plain.round() as i32)
+    }
+
+    pub fn bool(&self, param: &BoolParam) -> bool {
+        self.lookup(param.as_ptr()).map_or_else(|| param.value(), |plain| plain > 0.5)
+    }
+
+    pub fn enumeration<T: Enum + PartialEq + 'static>(&self, param: &EnumParam<T>) -> T {
+        self.lookup(param.as_ptr())
+            .map_or_else(|| param.value(), |plain| T::from_index(plain.round() as usize))
+    }
+}
@@ -0,0 +1,178 @@
+//! Oversampled synthesis
+//!
+//! Everything up to the master dynamics runs at `factor` times the host's rate, and the result
+//! is decimated back down. There is no matching polyphase upsampler because nothing at the
+//! host's rate has to be interpolated: there is no audio input, the noise excitation is
+//! generated at the oversampled rate and the parameters are smoothed once per host sample and
+//! held for its oversampled steps. The DSP compensates for the higher rate itself, so a patch
+//! sounds the same at every factor: filter cutoffs are set in Hz from the host's rate, delays
+//! are scaled by the factor and the noise is made louder by the power the decimation removes.
+
+use nih_plug::prelude::Enum;
+use std::f32::consts::PI;
+
+/// Taps of each halfband stage, must be of the form 4n + 3
+const HALFBAND_TAPS: usize = 31;
+
+/// Highest supported factor
+pub(crate) const MAX_OVERSAMPLING: usize = 8;
+
+const MAX_STAGES: usize = 3;
+
+#[derive(Enum, Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) enum OversamplingFactor {
+    #[id = "1x"]
+    #[name = "1x"]
+    X1,
+    #[id = "2x"]
+    #[name = "2x"]
+    X2,
+    #[id = "4x"]
+    #[name = "4x"]
+    X4,
+    #[id = "8x"]
+    #[name = "8x"]
+    X8,
+}
+
+impl OversamplingFactor {
+    pub fn factor(self) -> usize {
+        match self {
+            OversamplingFactor::X1 => 1,
+            OversamplingFactor::X2 => 2,
+            OversamplingFactor::X4 => 4,
+            OversamplingFactor::X8 => 8,
+        }
+    }
+
+    fn stages(self) -> usize {
+        self.factor().trailing_zeros() as usize
+    }
+}
+
+/// 2:1 halfband lowpass decimator. Only the output samples that are kept get computed and
+/// every other tap of a halfband filter is zero, so this touches about a quarter of the taps a
+/// naive filter would.
+struct HalfbandDecimator {
+    /// Non-zero, non-center taps, the filter is symmetric so only one side is stored
+    coeffs: [f32; (HALFBAND_TAPS + 1) / 4],
+    /// Input history, twice the filter length so every window is contiguous
+    history: [f32; HALFBAND_TAPS * 2],
+    pos: usize,
+}
+
+impl HalfbandDecimator {
+    fn new() -> Self {
+        let center = HALFBAND_TAPS / 2;
+        let mut coeffs = [0.0; (HALFBAND_TAPS + 1) / 4];
+        for (i, coeff) in coeffs.iter_mut().enumerate() {
+            // Offsets 1, 3, 5, ... from the center
+            let offset = (2 * i + 1) as f32;
+            let x = PI * offset / 2.0;
+            let sinc = 0.5 * x.sin() / x;
+
+            // Blackman window
+            let n = (center as f32 + offset) / (HALFBAND_TAPS - 1) as f32;
+            let window = 0.42 - 0.5 * (2.0 * PI * n).cos() + 0.08 * (4.0 * PI * n).cos();
+            *coeff = sinc * window;
+        }
+
+        // Normalize for unity gain at DC, the center tap is fixed at 0.5
+        let sum: f32 = coeffs.iter().sum::<f32>() * 2.0;
+        for coeff in &mut coeffs {
+            *coeff *= 0.5 / sum;
+        }
+
+        Self {
+            coeffs,
+            history: [0.0; HALFBAND_TAPS * 2],
+            pos: 0,
+        }
+    }
+
+    fn push(&mut self, input: f32) {
+        self.history[self.pos] = input;
+        self.history[self.pos + HALFBAND_TAPS] = input;
+        self.pos = (self.pos + 1) % HALFBAND_TAPS;
+    }
+
+    /// Take two input samples and produce one output sample
+    fn process(&mut self, first: f32, second: f32) -> f32 {
+        self.push(first);
+        self.push(second);
+
+        // Oldest sample of the window is at `pos`
+        let window = &self.history[self.pos..self.pos + HALFBAND_TAPS];
+        let center = HALFBAND_TAPS / 2;
+        let mut output = 0.5 * window[center];
+        for (i, coeff) in self.coeffs.iter().enumerate() {
+            let offset = 2 * i + 1;
+            output += coeff * (window[center - offset] + window[center + offset]);
+        }
+
+        output
+    }
+
+    fn reset(&mut self) {
+        self.history = [0.0; HALFBAND_TAPS * 2];
+        self.pos = 0;
+    }
+}
+
+/// Brings a block of `factor` oversampled samples back down to a single output sample
+///
+/// The drum is synthesized natively at the oversampled rate (there is no audio input to
+/// interpolate), so only the way back down needs filtering. This uses one polyphase halfband
+/// stage per doubling.
+pub(crate) struct Decimator {
+    oversampling: OversamplingFactor,
+    stages: [HalfbandDecimator; MAX_STAGES],
+}
+
+impl Decimator {
+    pub fn new() -> Self {
+        Self {
+            oversampling: OversamplingFactor::X1,
+            stages: std::array::from_fn(|_| HalfbandDecimator::new()),
+        }
+    }
+
+    pub fn set_oversampling(&mut self, oversampling: OversamplingFactor) {
+        if oversampling != self.oversampling {
+            self.oversampling = oversampling;
+            self.reset();
+        }
+    }
+
+    /// Latency of the filter cascade in output samples, rounded to the nearest sample
+    pub fn latency(&self) -> u32 {
+        // Every stage delays by half its length at its own input rate
+        let stage_delay = (HALFBAND_TAPS / 2) as f32;
+        let latency: f32 = (1..=self.oversampling.stages())
+            .map(|stage| stage_delay / (1 << stage) as f32)
+            .sum();
+
+        latency.round() as u32
+    }
+
+    /// Process `factor` samples, the first sample is the oldest
+    pub fn process(&mut self, block: &mut [f32]) -> f32 {
+        let mut len = block.len();
+        debug_assert_eq!(len, self.oversampling.factor());
+
+        for stage in self.stages.iter_mut().take(self.oversampling.stages()) {
+            len /= 2;
+            for i in 0..len {
+                block[i] = stage.process(block[2 * i], block[2 * i + 1]);
+            }
+        }
+
+        block[0]
+    }
+
+    pub fn reset(&mut self) {
+        for stage in &mut self.stages {
+            stage.reset();
+        }
+    }
+}
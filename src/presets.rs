@@ -0,0 +1,623 @@
+//! Preset files
+//!
+//! Presets are stored as pretty-printed JSON so they can be kept in version control, diffed and
+//! shared between hosts independently of the host's own state:
+//!
+//! ```json
+//! {
+//!   "format": "drum-synth-preset",
+//!   "format_version": 2,
+//!   "plugin_version": "0.1.0",
+//!   "state_version": 4,
+//!   "name": "Deep Tom",
+//!   "params": {
+//!     "gain": 1.0,
+//!     "res_delay_samples": 170.0,
+//!     "res_eq_lc_on": true,
+//!     "res_loop_filter": "2-Pole LP",
+//!     "noise_seed": 0
+//!   }
+//! }
+//! ```
+//!
+//! - `params` is keyed by parameter ID and contains every parameter of the plugin when
+//!   exported. Float parameters are stored as plain values in their own unit (seconds, Hz, dB,
+//!   linear gain, ...), integer parameters as integers, toggles as booleans and choices by
+//!   their display name.
+//! - Parameters missing from a file are set to their defaults and unknown IDs are ignored, so
+//!   files from older and newer versions of the plugin still load.
+//! - `format_version` only changes when the layout of the file itself changes.
+//!   `plugin_version` records which version of the plugin wrote the file.
+//! - `state_version` is the version of the parameters, like the one saved with the host's
+//!   state. Loading a file from an older version runs the same migrations as restoring an
+//!   older session, so the preset sounds the way it did. Files without it count as version 0,
+//!   unless they're in the current `format_version`, which always saves it. Those were written
+//!   by hand for the current version.
+
+use nih_plug::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::migrations::{self, STATE_VERSION, STATE_VERSION_KEY};
+use crate::overrides;
+use crate::DrumSynthParams;
+
+/// Written to every preset file so they can be recognized
+const FORMAT_NAME: &str = "drum-synth-preset";
+
+/// Version of the file layout described above. Version 2 added `state_version`.
+const FORMAT_VERSION: u32 = 2;
+
+/// A single parameter value as written to a preset file
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(untagged)]
+pub(crate) enum PresetValue {
+    Bool(bool),
+    Int(i64),
+    Float(f32),
+    Text(String),
+}
+
+impl PresetValue {
+    /// Convert a plain value to the representation for its parameter's type
+    ///
+    /// # Safety
+    ///
+    /// `param_ptr` needs to point to a live parameter.
+    unsafe fn from_plain(param_ptr: ParamPtr, plain: f32) -> Self {
+        match param_ptr {
+            ParamPtr::BoolParam(_) => PresetValue::Bool(plain > 0.5),
+            ParamPtr::IntParam(_) => PresetValue::Int(plain.round() as i64),
+            ParamPtr::EnumParam(_) => {
+                PresetValue::Text(param_ptr.normalized_value_to_string(param_ptr.preview_normalized(plain), false))
+            }
+            ParamPtr::FloatParam(_) => PresetValue::Float(plain),
+        }
+    }
+
+    /// The normalized value for `param_ptr`, or `None` if this value doesn't fit the parameter
+    ///
+    /// # Safety
+    ///
+    /// `param_ptr` needs to point to a live parameter.
+    unsafe fn to_normalized(&self, param_ptr: ParamPtr) -> Option<f32> {
+        match self {
+            PresetValue::Bool(value) => Some(param_ptr.preview_normalized(if *value { 1.0 } else { 0.0 })),
+            PresetValue::Int(value) => Some(param_ptr.preview_normalized(*value as f32)),
+            PresetValue::Float(value) => Some(param_ptr.preview_normalized(*value)),
+            PresetValue::Text(value) => param_ptr.string_to_normalized_value(value),
+        }
+    }
+
+    /// The value as it's saved in the host's state, which is what the migrations work on
+    fn to_param_value(&self) -> ParamValue {
+        match self {
+            PresetValue::Bool(value) => ParamValue::Bool(*value),
+            PresetValue::Int(value) => ParamValue::I32(*value as i32),
+            PresetValue::Float(value) => ParamValue::F32(*value),
+            PresetValue::Text(value) => ParamValue::String(value.clone()),
+        }
+    }
+
+    fn from_param_value(value: ParamValue) -> Self {
+        match value {
+            ParamValue::Bool(value) => PresetValue::Bool(value),
+            ParamValue::I32(value) => PresetValue::Int(value as i64),
+            ParamValue::F32(value) => PresetValue::Float(value),
+            ParamValue::String(value) => PresetValue::Text(value),
+        }
+    }
+}
+
+fn format_name() -> String {
+    FORMAT_NAME.to_owned()
+}
+
+fn plugin_version() -> String {
+    env!("CARGO_PKG_VERSION").to_owned()
+}
+
+/// A named set of parameter values, keyed by parameter ID. See the module documentation for
+/// the file format.
+#[derive(Serialize, Deserialize, Clone)]
+pub(crate) struct Preset {
+    #[serde(default = "format_name")]
+    pub format: String,
+    #[serde(default)]
+    pub format_version: u32,
+    #[serde(default)]
+    pub plugin_version: String,
+    /// Always set once the preset is loaded
+    #[serde(default)]
+    pub state_version: Option<u32>,
+    pub name: String,
+    pub params: BTreeMap<String, PresetValue>,
+}
+
+impl Preset {
+    /// Snapshot the current values of all parameters
+    pub fn capture(name: &str, params: &DrumSynthParams) -> Self {
+        // SAFETY: The pointers come straight from the params object we're holding a reference to
+        Self::from_params(name, params, |_, param_ptr| unsafe { param_ptr.unmodulated_plain_value() })
+    }
+
+    fn from_params(name: &str, params: &DrumSynthParams, mut plain_value: impl FnMut(&str, ParamPtr) -> f32) -> Self {
+        let values = params
+            .param_map()
+            .into_iter()
+            .map(|(id, param_ptr, _)| {
+                let plain = plain_value(&id, param_ptr);
+                // SAFETY: See above
+                (id, unsafe { PresetValue::from_plain(param_ptr, plain) })
+            })
+            .collect();
+
+        Self {
+            format: format_name(),
+            format_version: FORMAT_VERSION,
+            plugin_version: plugin_version(),
+            state_version: Some(STATE_VERSION),
+            name: name.to_owned(),
+            params: values,
+        }
+    }
+
+    /// Upgrade the parameters of a preset from an older version of the plugin with the same
+    /// migrations as the host's state
+    fn migrate(&mut self) {
+        let state_version = self.state_version.unwrap_or(0);
+        if state_version == STATE_VERSION {
+            return;
+        }
+
+        let mut state = PluginState {
+            version: self.plugin_version.clone(),
+            params: self
+                .params
+                .iter()
+                .map(|(id, value)| (id.clone(), value.to_param_value()))
+                .collect(),
+            fields: BTreeMap::from([(STATE_VERSION_KEY.to_owned(), state_version.to_string())]),
+        };
+        migrations::migrate(&mut state);
+
+        self.state_version = Some(migrations::state_version(&state));
+        self.params = state
+            .params
+            .into_iter()
+            .map(|(id, value)| (id, PresetValue::from_param_value(value)))
+            .collect();
+    }
+
+    /// Calls `set` with the normalized value this preset has for every parameter. Setting the
+    /// values is left to the caller since that has to go through the host.
+    pub fn apply(&self, params: &DrumSynthParams, mut set: impl FnMut(ParamPtr, f32)) {
+        for (id, param_ptr, _) in params.param_map() {
+            // SAFETY: See above
+            unsafe {
+                let normalized = match self.params.get(&id) {
+                    Some(value) => value.to_normalized(param_ptr).unwrap_or_else(|| {
+                        nih_warn!("Preset '{}' has an invalid value for '{}': {:?}", self.name, id, value);
+                        param_ptr.default_normalized_value()
+                    }),
+                    None => param_ptr.default_normalized_value(),
+                };
+                set(param_ptr, normalized);
+            }
+        }
+    }
+
+    /// Default patch with some parameters changed, used for the factory bank
+    fn factory(name: &str, overrides: &[(&str, f32)]) -> Self {
+        let preset = Self::from_params(name, &DrumSynthParams::default(), |id, param_ptr| {
+            overrides
+                .iter()
+                .find(|(override_id, _)| *override_id == id)
+                // SAFETY: See above
+                .map_or_else(|| unsafe { param_ptr.default_plain_value() }, |(_, value)| *value)
+        });
+        debug_assert!(
+            overrides.iter().all(|(id, _)| preset.params.contains_key(*id)),
+            "Unknown parameter in factory preset '{name}'"
+        );
+
+        preset
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Self::from_json(&fs::read(path)?)
+    }
+
+    /// Parse the contents of a preset file, upgrading it if it's from an older version
+    pub fn from_json(json: &[u8]) -> io::Result<Self> {
+        let mut preset = Self::parse(json)?;
+        preset.migrate();
+
+        Ok(preset)
+    }
+
+    /// Parse the contents of a preset file without upgrading it. `state_version` stays unset
+    /// if the file doesn't say which version it's from.
+    fn parse(json: &[u8]) -> io::Result<Self> {
+        let mut preset: Self = serde_json::from_slice(json).map_err(io::Error::from)?;
+        if preset.format != FORMAT_NAME {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Not a drum synth preset"));
+        }
+        if preset.format_version > FORMAT_VERSION {
+            nih_warn!(
+                "Preset '{}' uses format version {}, only version {} is supported",
+                preset.name,
+                preset.format_version,
+                FORMAT_VERSION
+            );
+        }
+        if preset.format_version >= FORMAT_VERSION && preset.state_version.is_none() {
+            preset.state_version = Some(STATE_VERSION);
+        }
+
+        Ok(preset)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut json = serde_json::to_vec_pretty(self).map_err(io::Error::from)?;
+        json.push(b'\n');
+        fs::write(path, json)
+    }
+}
+
+/// A sound for `DrumEngine`, stored in the same JSON format as preset files. Parameters the
+/// patch doesn't mention keep their defaults.
+#[derive(Clone)]
+pub struct Patch {
+    pub(crate) preset: Preset,
+}
+
+impl Patch {
+    /// Read a preset file
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Preset::load(path).map(|preset| Self { preset })
+    }
+
+    /// Parse a preset from JSON, for example one that's embedded in a game's assets. Patches
+    /// from older versions of the plugin are upgraded like preset files, so the JSON has to say
+    /// which version it's from with a `state_version`, or with the current `format_version`.
+    pub fn from_json(json: &str) -> io::Result<Self> {
+        let mut preset = Preset::parse(json.as_bytes())?;
+        if preset.state_version.is_none() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Patch has no state_version"));
+        }
+        preset.migrate();
+
+        Ok(Self { preset })
+    }
+
+    /// One of the plugin's factory presets, by name
+    pub fn factory(name: &str) -> Option<Self> {
+        factory_bank()
+            .into_iter()
+            .find(|preset| preset.name == name)
+            .map(|preset| Self { preset })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.preset.name
+    }
+}
+
+/// A patch resolved into the values the engine reads, so `DrumEngine::set_prepared_patch()` can
+/// switch to it without allocating
+#[derive(Clone)]
+pub struct PreparedPatch {
+    /// Plain values in `param_map()` order
+    pub(crate) values: Vec<f32>,
+}
+
+impl PreparedPatch {
+    /// Resolve `patch`. This allocates, so do it on another thread than the audio callback.
+    pub fn new(patch: &Patch) -> Self {
+        Self {
+            values: overrides::plain_values(&DrumSynthParams::default(), &patch.preset),
+        }
+    }
+}
+
+impl Default for Patch {
+    /// Every parameter at its default
+    fn default() -> Self {
+        Self {
+            preset: Preset::factory("Init", &[]),
+        }
+    }
+}
+
+/// Built-in starting points. Enum values are variant indices.
+fn factory_bank() -> Vec<Preset> {
+    vec![
+        Preset::factory("Tight Funk Snare", &[
+            ("tr_decay", 0.015),
+            ("tr_level", 0.9),
+            ("tr_eq_freq", 900.0),
+            ("tr_eq_gain", 4.0),
+            ("res_delay_samples", 32.0),
+            ("res_feedback", -0.6),
+            ("res_damping", 0.4),
+            ("snare_decay", 0.12),
+            ("snare_level", 0.4),
+            ("snare_eq_freq", 4000.0),
+        ]),
+        Preset::factory("Trashy 2x4 Snare", &[
+            ("tr_decay", 0.012),
+            ("tr_level", 1.0),
+            ("res_delay_samples", 58.0),
+            ("res_feedback", -0.93),
+            ("res_damping", 0.2),
+            ("res_loop_filter", 2.0), // High pass
+            ("snare_decay", 0.25),
+            ("snare_release", 0.85),
+            ("snare_level", 0.5),
+            ("snare_eq_gain", 9.0),
+            ("dyn_comp_threshold", -24.0),
+            ("dyn_comp_ratio", 6.0),
+            ("dyn_comp_makeup", 6.0),
+        ]),
+        Preset::factory("Deep Tom", &[
+            ("tr_decay", 0.02),
+            ("tr_eq_freq", 300.0),
+            ("res_delay_samples", 170.0),
+            ("res_feedback", -0.96),
+            ("res_damping", 0.65),
+            ("res_loop_filter", 1.0), // 2-pole LP
+            ("res_pitch_amount", 5.0),
+            ("res_pitch_decay", 0.08),
+            ("snare_level", 0.0),
+            ("res_eq_lc_on", 1.0),
+            ("res_eq_lc_freq", 50.0),
+        ]),
+        Preset::factory("808-ish Kick", &[
+            ("tr_level", 0.5),
+            ("tr_eq_freq", 150.0),
+            ("tr_eq_gain", 6.0),
+            ("res_delay_samples", 200.0),
+            ("res_feedback", -0.98),
+            ("res_damping", 0.85),
+            ("res_loop_filter", 1.0), // 2-pole LP
+            ("res_pitch_amount", 14.0),
+            ("res_pitch_decay", 0.06),
+            ("res_pitch_curve", 0.7),
+            ("snare_level", 0.0),
+        ]),
+        Preset::factory("Rim", &[
+            ("tr_decay", 0.01),
+            ("tr_release", 0.01),
+            ("tr_level", 1.0),
+            ("tr_eq_freq", 1500.0),
+            ("tr_eq_gain", 6.0),
+            ("res_delay_samples", 12.0),
+            ("res_feedback", -0.5),
+            ("res_damping", 0.2),
+            ("snare_level", 0.0),
+        ]),
+    ]
+}
+
+/// Directory user presets are saved to
+fn user_preset_dir() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("Drum Synth").join("Presets"))
+}
+
+/// `name` with anything that's not safe in a file name replaced by underscores. Letters and
+/// digits are always kept, `keep` lists the other characters that are.
+pub(crate) fn safe_file_stem(name: &str, keep: &[char]) -> String {
+    name.trim()
+        .chars()
+        .map(|c| if c.is_alphanumeric() || keep.contains(&c) { c } else { '_' })
+        .collect()
+}
+
+/// File name for a preset name
+pub(crate) fn preset_file_name(name: &str) -> String {
+    format!("{}.json", safe_file_stem(name, &[' ', '-', '_']))
+}
+
+pub(crate) struct PresetEntry {
+    pub preset: Preset,
+    pub factory: bool,
+}
+
+/// The factory bank followed by the user's presets, used by the editor's preset browser
+pub(crate) struct PresetManager {
+    entries: Vec<PresetEntry>,
+}
+
+impl PresetManager {
+    pub fn new() -> Self {
+        let mut manager = Self {
+            entries: Vec::new(),
+        };
+        manager.refresh();
+        manager
+    }
+
+    /// Reload the factory bank and rescan the user preset directory
+    pub fn refresh(&mut self) {
+        self.entries = factory_bank()
+            .into_iter()
+            .map(|preset| PresetEntry { preset, factory: true })
+            .collect();
+
+        let Some(dir) = user_preset_dir() else {
+            return;
+        };
+        let Ok(files) = fs::read_dir(&dir) else {
+            return;
+        };
+
+        let mut user_presets: Vec<Preset> = files
+            .filter_map(|file| file.ok())
+            .map(|file| file.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .filter_map(|path| match Preset::load(&path) {
+                Ok(preset) => Some(preset),
+                Err(err) => {
+                    nih_error!("Could not load preset '{}': {}", path.display(), err);
+                    None
+                }
+            })
+            .collect();
+        user_presets.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
+
+        self.entries
+            .extend(user_presets.into_iter().map(|preset| PresetEntry { preset, factory: false }));
+    }
+
+    pub fn entries(&self) -> &[PresetEntry] {
+        &self.entries
+    }
+
+    pub fn get(&self, index: usize) -> Option<&PresetEntry> {
+        self.entries.get(index)
+    }
+
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.entries.iter().position(|entry| entry.preset.name == name)
+    }
+
+    /// Index after `current`, wrapping around
+    pub fn next_index(&self, current: Option<usize>) -> Option<usize> {
+        if self.entries.is_empty() {
+            return None;
+        }
+
+        Some(current.map_or(0, |index| (index + 1) % self.entries.len()))
+    }
+
+    /// Index before `current`, wrapping around
+    pub fn previous_index(&self, current: Option<usize>) -> Option<usize> {
+        if self.entries.is_empty() {
+            return None;
+        }
+
+        let len = self.entries.len();
+        Some(current.map_or(len - 1, |index| (index + len - 1) % len))
+    }
+
+    /// Copy a preset file into the user preset directory. Returns the preset's new index.
+    pub fn import_preset(&mut self, path: &Path) -> io::Result<usize> {
+        let preset = Preset::load(path)?;
+        self.save_user_preset(&preset)
+    }
+
+    /// Write a preset to the user preset directory, overwriting any preset with the same name.
+    /// Returns the preset's new index.
+    pub fn save_user_preset(&mut self, preset: &Preset) -> io::Result<usize> {
+        let dir = user_preset_dir()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No user data directory"))?;
+        fs::create_dir_all(&dir)?;
+        preset.save(&dir.join(preset_file_name(&preset.name)))?;
+
+        self.refresh();
+        self.entries
+            .iter()
+            .position(|entry| !entry.factory && entry.preset.name == preset.name)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Saved preset did not load back"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ENVELOPE_CURVE_RANGE;
+
+    /// A preset file with only `params`, from `state_version` or from before versioning
+    fn preset_json(state_version: Option<u32>, params: &str) -> Vec<u8> {
+        let version = state_version.map_or(String::new(), |version| format!(r#""state_version": {version}, "#));
+        format!(r#"{{"format": "{FORMAT_NAME}", {version}"name": "Test", "params": {{{params}}}}}"#).into_bytes()
+    }
+
+    #[test]
+    fn presets_round_trip() {
+        let preset = Preset::capture("Round Trip", &DrumSynthParams::default());
+        let path = std::env::temp_dir().join(format!("drum-synth-round-trip-{}.json", std::process::id()));
+        preset.save(&path).unwrap();
+        let loaded = Preset::load(&path);
+        let _ = fs::remove_file(&path);
+
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.name, preset.name);
+        assert_eq!(loaded.state_version, Some(STATE_VERSION));
+        assert_eq!(loaded.params, preset.params);
+    }
+
+    #[test]
+    fn current_presets_are_not_migrated() {
+        let preset = Preset::from_json(&preset_json(Some(STATE_VERSION), r#""vel_sens": 0.8"#)).unwrap();
+        assert_eq!(preset.params.get("vel_sens"), Some(&PresetValue::Float(0.8)));
+        assert!(!preset.params.contains_key("dyn_limiter"));
+    }
+
+    #[test]
+    fn unversioned_presets_are_migrated() {
+        let preset = Preset::from_json(&preset_json(None, "")).unwrap();
+        assert_eq!(preset.state_version, Some(STATE_VERSION));
+        assert_eq!(preset.params.get("dyn_limiter"), Some(&PresetValue::Bool(false)));
+    }
+
+    #[test]
+    fn current_format_without_state_version_is_current() {
+        let json = format!(
+            r#"{{"format_version": {FORMAT_VERSION}, "name": "Test", "params": {{"vel_sens": 0.8}}}}"#
+        );
+        let preset = Preset::from_json(json.as_bytes()).unwrap();
+        assert_eq!(preset.state_version, Some(STATE_VERSION));
+        assert_eq!(preset.params.get("vel_sens"), Some(&PresetValue::Float(0.8)));
+        assert!(!preset.params.contains_key("dyn_limiter"));
+    }
+
+    #[test]
+    fn patches_need_a_version() {
+        let json = |version: &str| format!(r#"{{{version}"name": "Test", "params": {{}}}}"#);
+        assert!(Patch::from_json(&json("")).is_err());
+        assert!(Patch::from_json(&json(r#""state_version": 1, "#)).is_ok());
+        assert!(Patch::from_json(&json(&format!(r#""format_version": {FORMAT_VERSION}, "#))).is_ok());
+    }
+
+    #[test]
+    fn migrated_presets_keep_their_other_values() {
+        let preset = Preset::from_json(&preset_json(
+            Some(1),
+            r#""res_loop_filter": "2-Pole LP", "noise_seed": 12, "res_eq_lc_on": true, "gain": 0.5"#,
+        ))
+        .unwrap();
+        assert_eq!(preset.params.get("res_loop_filter"), Some(&PresetValue::Text("2-Pole LP".to_owned())));
+        assert_eq!(preset.params.get("noise_seed"), Some(&PresetValue::Int(12)));
+        assert_eq!(preset.params.get("res_eq_lc_on"), Some(&PresetValue::Bool(true)));
+        assert_eq!(preset.params.get("gain"), Some(&PresetValue::Float(0.5)));
+    }
+
+    #[test]
+    fn old_preset_releases_are_converted() {
+        let preset = Preset::from_json(&preset_json(Some(1), r#""tr_release": 0.2"#)).unwrap();
+        assert_eq!(preset.params.get("tr_release"), Some(&PresetValue::Float(0.2 * ENVELOPE_CURVE_RANGE)));
+        assert_eq!(preset.params.get("tr_release_curve"), Some(&PresetValue::Float(1.0)));
+    }
+
+    #[test]
+    fn old_preset_snare_wires_keep_their_envelope() {
+        let preset = Preset::from_json(&preset_json(Some(2), r#""snare_decay": 0.3, "snare_sustain": 0.6"#)).unwrap();
+        assert_eq!(preset.params.get("snare_hold"), Some(&PresetValue::Float(0.0)));
+        assert_eq!(preset.params.get("snare_sustain"), Some(&PresetValue::Float(0.0)));
+        assert_eq!(
+            preset.params.get("snare_release"),
+            Some(&PresetValue::Float(0.3 * 0.5 * ENVELOPE_CURVE_RANGE))
+        );
+    }
+
+    #[test]
+    fn old_presets_ignore_midi_velocity() {
+        let preset = Preset::from_json(&preset_json(Some(3), "")).unwrap();
+        assert_eq!(preset.params.get("vel_sens"), Some(&PresetValue::Float(0.0)));
+    }
+}
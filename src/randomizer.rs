@@ -0,0 +1,109 @@
+use nih_plug::prelude::*;
+
+use crate::morph::layer_float_params;
+use crate::noise::Pcg32;
+use crate::DrumSynthParams;
+
+/// Plain value ranges that stay musical, usually narrower than the parameters' full ranges
+const RANGES: &[(&str, f32, f32)] = &[
+    ("tr_attack", 0.0001, 0.003),
+    ("tr_hold", 0.0, 0.005),
+    ("tr_decay", 0.01, 0.08),
+    ("tr_release", 0.02, 0.3),
+    ("tr_attack_curve", -0.5, 0.5),
+    ("tr_decay_curve", 0.3, 1.0),
+    ("tr_release_curve", 0.3, 1.0),
+    ("tr_level", 0.5, 1.0),
+    ("res_delay_samples", 15.0, 150.0),
+    ("res_feedback", -0.97, -0.5),
+    ("res_damping", 0.2, 0.8),
+    ("res_stiffness", 0.0, 0.4),
+    ("res_level", 0.5, 1.0),
+    ("res_pitch_amount", 0.0, 12.0),
+    ("res_pitch_decay", 0.01, 0.2),
+    ("snare_attack", 0.0001, 0.003),
+    ("snare_hold", 0.0, 0.01),
+    ("snare_decay", 0.05, 0.35),
+    ("snare_sustain", 0.0, 0.1),
+    ("snare_release", 0.1, 1.0),
+    ("snare_release_curve", 0.3, 1.0),
+    ("snare_delay", 0.0, 0.008),
+    ("snare_attack_curve", -0.5, 0.5),
+    ("snare_decay_curve", 0.3, 1.0),
+    ("snare_level", 0.1, 0.7),
+    // The first EQ peak is each layer's main tone control, so its range depends on the layer
+    ("tr_eq_freq", 150.0, 2000.0),
+    ("res_eq_freq", 80.0, 1200.0),
+    ("snare_eq_freq", 1500.0, 8000.0),
+];
+
+/// Same as `RANGES`, for every layer's EQ. Keyed by the ID without the layer prefix.
+const EQ_RANGES: &[(&str, f32, f32)] = &[
+    ("eq_lc_freq", 20.0, 150.0),
+    ("eq_ls_freq", 60.0, 400.0),
+    ("eq_ls_gain", -6.0, 6.0),
+    ("eq_gain", -6.0, 9.0),
+    ("eq_q", 0.5, 3.0),
+    ("eq2_freq", 1000.0, 8000.0),
+    ("eq2_gain", -6.0, 6.0),
+    ("eq2_q", 0.5, 3.0),
+    ("eq_hs_freq", 3000.0, 12000.0),
+    ("eq_hs_gain", -6.0, 6.0),
+    ("eq_hc_freq", 6000.0, 20000.0),
+];
+
+/// Normalized range used for parameters without an entry in the tables above
+const DEFAULT_NORMALIZED_RANGE: (f32, f32) = (0.2, 0.8);
+
+/// Which parameters a randomization touches
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RandomizeScope {
+    All,
+    /// A single layer, by its parameter group
+    Layer(&'static str),
+}
+
+/// The parameter's ID if the randomizer touches it, used for the lock toggles
+pub(crate) fn randomizable_id(params: &DrumSynthParams, param_ptr: ParamPtr) -> Option<String> {
+    layer_float_params(params).find(|(_, ptr, _)| *ptr == param_ptr).map(|(id, _, _)| id)
+}
+
+/// The normalized range a parameter gets randomized in
+///
+/// # Safety
+///
+/// `param_ptr` needs to point to a live parameter.
+unsafe fn normalized_range(id: &str, param_ptr: ParamPtr) -> (f32, f32) {
+    let eq_id = id.split_once('_').map_or(id, |(_, rest)| rest);
+    let range = RANGES
+        .iter()
+        .find(|(range_id, _, _)| *range_id == id)
+        .or_else(|| EQ_RANGES.iter().find(|(range_id, _, _)| *range_id == eq_id));
+
+    match range {
+        Some((_, min, max)) => (param_ptr.preview_normalized(*min), param_ptr.preview_normalized(*max)),
+        None => DEFAULT_NORMALIZED_RANGE,
+    }
+}
+
+/// Roll new normalized values for the parameters in `scope` that aren't in `locked`. Returns the
+/// parameters with their new values, setting them is left to the caller.
+pub(crate) fn randomize(
+    params: &DrumSynthParams,
+    scope: RandomizeScope,
+    locked: &[String],
+    rng: &mut Pcg32,
+) -> Vec<(ParamPtr, f32)> {
+    layer_float_params(params)
+        .filter(|(_, _, group)| match scope {
+            RandomizeScope::All => true,
+            RandomizeScope::Layer(layer) => group.starts_with(layer),
+        })
+        .filter(|(id, _, _)| !locked.contains(id))
+        .map(|(id, param_ptr, _)| {
+            // SAFETY: The pointers come straight from the params object we're holding a reference to
+            let (min, max) = unsafe { normalized_range(&id, param_ptr) };
+            (param_ptr, min + (max - min) * rng.next_f32())
+        })
+        .collect()
+}
@@ -0,0 +1,90 @@
+use nih_plug::prelude::*;
+
+use crate::overrides::ParamOverrides;
+use crate::RatchetParams;
+
+/// Most hits a single note can turn into
+pub(crate) const MAX_RATCHET_HITS: usize = 16;
+
+/// Slowest tempo rolls are spaced for, the bottom of the sequencer's tempo range. Hosts can
+/// report a tempo of zero, which would push the hits out forever.
+const MIN_TEMPO: f64 = 40.0;
+
+/// What a single incoming note turns into
+#[derive(Enum, Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) enum RatchetMode {
+    /// Just the hit
+    #[id = "off"]
+    #[name = "Off"]
+    Off,
+    /// A grace note followed by the hit
+    #[id = "flam"]
+    #[name = "Flam"]
+    Flam,
+    /// Two quick grace notes followed by the hit
+    #[id = "drag"]
+    #[name = "Drag"]
+    Drag,
+    /// A run of evenly spaced hits at the roll rate
+    #[id = "roll"]
+    #[name = "Roll"]
+    Roll,
+}
+
+/// Level of a roll's hit, `ramp` goes from -1.0 (fading out to silence) to 1.0 (fading in from
+/// silence)
+fn roll_gain(index: u32, count: u32, ramp: f32) -> f32 {
+    let pos = if count > 1 {
+        index as f32 / (count - 1) as f32
+    } else {
+        1.0
+    };
+
+    if ramp >= 0.0 {
+        1.0 - ramp * (1.0 - pos)
+    } else {
+        1.0 + ramp * pos
+    }
+}
+
+/// Expand a note into its hits. `hit` is called with every hit's gain on top of the note's
+/// velocity and its delay in samples. Returns half the time between the hits, which is how long
+/// the last one is held when the note ends before it starts.
+pub(crate) fn schedule(
+    params: &RatchetParams,
+    overrides: &ParamOverrides,
+    tempo: f64,
+    sample_rate: f32,
+    mut hit: impl FnMut(f32, u32),
+) -> u32 {
+    let grace_samples = (overrides.float(&params.grace_time) * sample_rate) as u32;
+    let grace_gain = overrides.float(&params.grace_level) / 100.0;
+
+    match overrides.enumeration(&params.mode) {
+        RatchetMode::Off => {
+            hit(1.0, 0);
+            0
+        }
+        RatchetMode::Flam => {
+            hit(grace_gain, 0);
+            hit(1.0, grace_samples);
+            grace_samples / 2
+        }
+        RatchetMode::Drag => {
+            hit(grace_gain, 0);
+            hit(grace_gain, grace_samples / 2);
+            hit(1.0, grace_samples);
+            grace_samples / 4
+        }
+        RatchetMode::Roll => {
+            let count = overrides.int(&params.roll_count) as u32;
+            let ramp = overrides.float(&params.roll_ramp) / 100.0;
+            let tempo = tempo.max(MIN_TEMPO);
+            let spacing = overrides.enumeration(&params.roll_rate).beats() * 60.0 / tempo * sample_rate as f64;
+            for index in 0..count {
+                hit(roll_gain(index, count, ramp), (index as f64 * spacing) as u32);
+            }
+            (spacing / 2.0) as u32
+        }
+    }
+}
@@ -0,0 +1,289 @@
+//! Offline rendering without a host or an audio device
+
+use nih_plug::prelude::*;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::modulation::TransportInfo;
+use crate::presets::{safe_file_stem, Preset};
+use crate::{DrumEngine, BLOCK_SIZE};
+
+/// The end of a hit is cut off where it stays below this level
+const SILENCE_THRESHOLD_DB: f32 = -80.0;
+
+/// Length of the fade at the end of a trimmed hit in seconds
+const TRIM_FADE: f32 = 0.005;
+
+/// Bounces from the editor are this long at most before trimming, in seconds
+const BOUNCE_LENGTH: f32 = 4.0;
+
+const BOUNCE_SAMPLE_RATE: f32 = 48000.0;
+
+pub(crate) struct RenderSettings {
+    pub note: u8,
+    /// From 0.0 to 1.0
+    pub velocity: f32,
+    /// Time until the note off in seconds
+    pub gate: f32,
+    /// Length of the rendered audio in seconds
+    pub length: f32,
+    pub sample_rate: f32,
+    /// Picks the noise and the humanization, the same seed renders the same audio
+    pub seed: u64,
+}
+
+/// Sample formats for WAV files
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum WavFormat {
+    Int16,
+    Int24,
+    Float32,
+}
+
+impl WavFormat {
+    /// The format for a bit depth, 32 bits are written as floats
+    pub fn from_bits(bits: u16) -> Option<Self> {
+        match bits {
+            16 => Some(WavFormat::Int16),
+            24 => Some(WavFormat::Int24),
+            32 => Some(WavFormat::Float32),
+            _ => None,
+        }
+    }
+}
+
+/// Render a single note of a preset, or of the default patch without one. The plugin's latency
+/// is compensated so the hit starts on the first sample. Rendering runs in offline mode, so it
+/// uses the offline oversampling factor.
+pub(crate) fn render_note(preset: Option<&Preset>, settings: &RenderSettings) -> Vec<f32> {
+    let mut synth = DrumEngine::default();
+    if let Some(preset) = preset {
+        synth.overrides.load(&synth.params, preset);
+    }
+    synth.prepare(settings.sample_rate, ProcessMode::Offline);
+    synth.reset();
+    synth.seed(settings.seed);
+
+    let latency = synth.latency() as usize;
+    let length = (settings.length * settings.sample_rate) as usize;
+    let gate = (settings.gate * settings.sample_rate) as usize;
+    // Without a transport the sequencer stays off and rolls use the sequencer's tempo
+    let transport = TransportInfo {
+        tempo: None,
+        pos_beats: None,
+    };
+
+    let mut output = Vec::with_capacity(latency + length);
+    for sample_id in 0..latency + length {
+        if sample_id % BLOCK_SIZE == 0 {
+            synth.begin_block(transport, false);
+        }
+
+        if sample_id == 0 {
+            synth.trigger(settings.note, settings.velocity);
+        }
+        if sample_id == gate {
+            synth.release(settings.note);
+        }
+
+        output.push(synth.render_sample());
+    }
+
+    output.drain(..latency);
+    output
+}
+
+/// Cut off the silence at the end of a hit and fade out what's left of the tail. The start is
+/// kept so the hits stay in time.
+pub(crate) fn trim_silence(samples: &mut Vec<f32>, sample_rate: f32) {
+    let threshold = util::db_to_gain(SILENCE_THRESHOLD_DB);
+    let end = samples
+        .iter()
+        .rposition(|sample| sample.abs() > threshold)
+        .map_or(0, |pos| pos + 1);
+    samples.truncate(end);
+
+    let fade = ((TRIM_FADE * sample_rate) as usize).min(samples.len());
+    let fade_start = samples.len() - fade;
+    for (index, sample) in samples[fade_start..].iter_mut().enumerate() {
+        *sample *= 1.0 - (index + 1) as f32 / fade as f32;
+    }
+}
+
+/// Base for the sample file names, anything that's not safe in a file name or a mapping file
+/// gets replaced
+pub(crate) fn sample_base_name(name: &str) -> String {
+    let name = safe_file_stem(name, &['-']);
+    if name.is_empty() {
+        String::from("drum")
+    } else {
+        name
+    }
+}
+
+/// Write mono samples to a WAV file
+pub(crate) fn write_wav(
+    path: &Path,
+    samples: &[f32],
+    sample_rate: u32,
+    format: WavFormat,
+) -> hound::Result<()> {
+    let (bits_per_sample, sample_format) = match format {
+        WavFormat::Int16 => (16, hound::SampleFormat::Int),
+        WavFormat::Int24 => (24, hound::SampleFormat::Int),
+        WavFormat::Float32 => (32, hound::SampleFormat::Float),
+    };
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate,
+        bits_per_sample,
+        sample_format,
+    };
+
+    let mut writer = hound::WavWriter::create(path, spec)?;
+    for &sample in samples {
+        match format {
+            WavFormat::Int16 => {
+                writer.write_sample((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16)?
+            }
+            WavFormat::Int24 => {
+                writer.write_sample((sample.clamp(-1.0, 1.0) * 8_388_607.0) as i32)?
+            }
+            WavFormat::Float32 => writer.write_sample(sample)?,
+        }
+    }
+
+    writer.finalize()
+}
+
+/// Directory the editor's bounces are written to. They're kept around since a DAW may keep
+/// referring to the file instead of copying it.
+fn bounce_dir() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("Drum Synth").join("Bounces"))
+}
+
+/// Renders the editor's patch to a WAV file on a background thread, away from the audio thread
+pub struct BounceTask {
+    preset: Preset,
+    /// Called with the file once it's written, or with `None` if that failed
+    done: Box<dyn FnOnce(Option<PathBuf>) + Send>,
+}
+
+impl BounceTask {
+    pub(crate) fn new(preset: Preset, done: impl FnOnce(Option<PathBuf>) + Send + 'static) -> Self {
+        Self {
+            preset,
+            done: Box::new(done),
+        }
+    }
+
+    pub(crate) fn run(self) {
+        let file = match bounce(&self.preset) {
+            Ok(file) => Some(file),
+            Err(err) => {
+                nih_error!("Could not bounce '{}': {}", self.preset.name, err);
+                None
+            }
+        };
+
+        (self.done)(file);
+    }
+}
+
+/// Render a full velocity hit of the preset with its tail trimmed, written to a new file in the
+/// bounce directory
+fn bounce(preset: &Preset) -> io::Result<PathBuf> {
+    let dir =
+        bounce_dir().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No data directory"))?;
+    fs::create_dir_all(&dir)?;
+
+    // Earlier bounces of the same preset are never overwritten
+    let base_name = sample_base_name(&preset.name);
+    let path = (1..)
+        .map(|index| dir.join(format!("{base_name}_{index}.wav")))
+        .find(|path| !path.exists())
+        .unwrap();
+
+    let settings = RenderSettings {
+        note: 60,
+        velocity: 1.0,
+        gate: 0.1,
+        length: BOUNCE_LENGTH,
+        sample_rate: BOUNCE_SAMPLE_RATE,
+        seed: 0,
+    };
+    let mut samples = render_note(Some(preset), &settings);
+    trim_silence(&mut samples, settings.sample_rate);
+
+    write_wav(
+        &path,
+        &samples,
+        settings.sample_rate as u32,
+        WavFormat::Int24,
+    )
+    .map_err(io::Error::other)?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migrations::STATE_VERSION;
+
+    fn render(params: &str, seed: u64) -> Vec<f32> {
+        let json = format!(
+            r#"{{"name": "Test", "state_version": {STATE_VERSION}, "params": {{{params}}}}}"#
+        );
+        let preset = Preset::from_json(json.as_bytes()).unwrap();
+        let settings = RenderSettings {
+            note: 60,
+            velocity: 1.0,
+            gate: 0.1,
+            length: 0.5,
+            sample_rate: 48000.0,
+            seed,
+        };
+
+        render_note(Some(&preset), &settings)
+    }
+
+    fn rms_db(samples: &[f32]) -> f32 {
+        let power =
+            samples.iter().map(|sample| sample * sample).sum::<f32>() / samples.len() as f32;
+        util::gain_to_db(power.sqrt())
+    }
+
+    /// Offline renders oversample by default, they should still sound like the realtime patch
+    #[test]
+    fn oversampling_keeps_the_level_and_decay() {
+        for loop_filter in [
+            r#""res_loop_filter": "1-Pole LP", "res_damping": 0.7"#,
+            r#""res_loop_filter": "2-Pole LP""#,
+        ] {
+            let native = render(
+                &format!(r#"{loop_filter}, "oversampling_offline": "1x""#),
+                0,
+            );
+            for factor in ["2x", "4x"] {
+                let oversampled = render(
+                    &format!(r#"{loop_filter}, "oversampling_offline": "{factor}""#),
+                    0,
+                );
+
+                // The whole hit, and the tail on its own to catch a longer or shorter ring
+                let tail = native.len() / 2;
+                for (native, oversampled) in [
+                    (&native[..], &oversampled[..]),
+                    (&native[tail..], &oversampled[tail..]),
+                ] {
+                    let difference = rms_db(oversampled) - rms_db(native);
+                    assert!(
+                        difference.abs() < 2.0,
+                        "{factor} with {loop_filter} is {difference:.2} dB off"
+                    );
+                }
+            }
+        }
+    }
+}
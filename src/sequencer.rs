@@ -0,0 +1,231 @@
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::Ordering;
+
+use crate::modulation::TransportInfo;
+use crate::noise::Pcg32;
+use crate::overrides::ParamOverrides;
+use crate::SequencerParams;
+
+/// Number of steps stored in a pattern, the length parameter decides how many of them play
+pub(crate) const MAX_STEPS: usize = 64;
+
+/// Seed for the step probability rolls
+const PROBABILITY_SEED: u64 = 0x5E9;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct Step {
+    pub active: bool,
+    /// From 0.0 to 1.0, like a MIDI note's velocity
+    pub velocity: f32,
+    pub accent: bool,
+    /// Play a quieter grace note ahead of the hit
+    pub flam: bool,
+    /// Chance of the step playing, from 0.0 to 1.0
+    pub probability: f32,
+}
+
+impl Default for Step {
+    fn default() -> Self {
+        Self {
+            active: false,
+            velocity: 0.8,
+            accent: false,
+            flam: false,
+            probability: 1.0,
+        }
+    }
+}
+
+/// The sequencer's steps. Patterns saved with fewer than `MAX_STEPS` steps are padded with
+/// empty ones.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Pattern {
+    pub steps: Vec<Step>,
+}
+
+impl Default for Pattern {
+    /// A backbeat on two and four
+    fn default() -> Self {
+        let mut pattern = Self {
+            steps: vec![Step::default(); MAX_STEPS],
+        };
+        for index in (4..MAX_STEPS).step_by(8) {
+            pattern.steps[index].active = true;
+        }
+
+        pattern
+    }
+}
+
+impl Pattern {
+    pub fn step(&self, index: usize) -> Step {
+        self.steps.get(index).copied().unwrap_or_default()
+    }
+
+    pub fn step_mut(&mut self, index: usize) -> &mut Step {
+        if self.steps.len() <= index {
+            self.steps.resize(index + 1, Step::default());
+        }
+
+        &mut self.steps[index]
+    }
+}
+
+/// Change a step from the editor and let the audio thread know about it
+pub(crate) fn edit_step(params: &SequencerParams, index: usize, edit: impl FnOnce(&mut Step)) {
+    edit(params.pattern.write().unwrap().step_mut(index));
+    params.pattern_generation.fetch_add(1, Ordering::Release);
+}
+
+/// A step that starts on the current sample
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct StepTrigger {
+    pub velocity: f32,
+    pub accent: bool,
+    pub flam: bool,
+}
+
+/// Audio thread side of the step sequencer. Follows the host's song position, or runs on its
+/// own clock where there is no host.
+pub(crate) struct Sequencer {
+    steps: [Step; MAX_STEPS],
+    generation: u32,
+    rng: Pcg32,
+
+    running: bool,
+    length: i64,
+    /// Length of a step in quarter notes
+    step_beats: f64,
+    /// Position in quarter notes and how far it moves every sample
+    pos_beats: f64,
+    beats_per_sample: f64,
+    /// The last step that was reached, counted from the start of the song so every step only
+    /// fires once
+    last_step: Option<i64>,
+}
+
+impl Sequencer {
+    pub fn new() -> Self {
+        Self {
+            steps: [Step::default(); MAX_STEPS],
+            // Never matches the params' generation so the first block loads the pattern
+            generation: u32::MAX,
+            rng: Pcg32::new(PROBABILITY_SEED),
+            running: false,
+            length: 16,
+            step_beats: 0.25,
+            pos_beats: 0.0,
+            beats_per_sample: 0.0,
+            last_step: None,
+        }
+    }
+
+    /// Reload the pattern. Doesn't block, if the editor is writing to it right now this gets
+    /// retried on the next block.
+    pub fn refresh(&mut self, params: &SequencerParams) {
+        let generation = params.pattern_generation.load(Ordering::Acquire);
+        let Ok(pattern) = params.pattern.try_read() else {
+            return;
+        };
+
+        for (index, step) in self.steps.iter_mut().enumerate() {
+            *step = pattern.step(index);
+        }
+        self.generation = generation;
+    }
+
+    /// Pick up pattern edits and set up the clock for the next block, called once per block.
+    /// With `internal_clock` the sequencer runs at its own tempo whenever it's switched on,
+    /// otherwise it plays along with the host's transport.
+    pub fn configure(
+        &mut self,
+        params: &SequencerParams,
+        overrides: &ParamOverrides,
+        transport: TransportInfo,
+        internal_clock: bool,
+        sample_rate: f32,
+    ) {
+        if params.pattern_generation.load(Ordering::Acquire) != self.generation {
+            self.refresh(params);
+        }
+
+        self.length = overrides.int(&params.length) as i64;
+        self.step_beats = overrides.enumeration(&params.rate).beats();
+
+        let enabled = overrides.bool(&params.enabled);
+        let was_running = self.running;
+        if internal_clock {
+            self.running = enabled;
+            // The internal clock starts from the top every time the sequencer is switched on
+            if self.running && !was_running {
+                self.pos_beats = 0.0;
+            }
+            self.beats_per_sample = overrides.float(&params.tempo) as f64 / 60.0 / sample_rate as f64;
+        } else {
+            match (transport.tempo, transport.pos_beats) {
+                (Some(tempo), Some(pos_beats)) if enabled => {
+                    self.running = true;
+                    self.pos_beats = pos_beats;
+                    self.beats_per_sample = tempo / 60.0 / sample_rate as f64;
+                }
+                _ => self.running = false,
+            }
+        }
+
+        if !self.running {
+            self.last_step = None;
+        }
+    }
+
+    /// Advance by one sample, returns the step that starts on it if it should play
+    pub fn next_sample(&mut self) -> Option<StepTrigger> {
+        if !self.running {
+            return None;
+        }
+
+        let pos_beats = self.pos_beats;
+        self.pos_beats += self.beats_per_sample;
+
+        let step = (pos_beats / self.step_beats).floor() as i64;
+        if self.last_step == Some(step) {
+            return None;
+        }
+        self.last_step = Some(step);
+
+        // Starting playback in the middle of a step waits for the next one
+        if pos_beats - step as f64 * self.step_beats > self.beats_per_sample {
+            return None;
+        }
+
+        let step = self.steps[step.rem_euclid(self.length) as usize];
+        if !step.active || self.rng.next_f32() >= step.probability {
+            return None;
+        }
+
+        Some(StepTrigger {
+            velocity: step.velocity,
+            accent: step.accent,
+            flam: step.flam,
+        })
+    }
+
+    /// Whether steps can play, either along with the host or on the internal clock
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    /// Length of a step in samples at the current tempo
+    pub fn step_samples(&self) -> f64 {
+        if self.beats_per_sample > 0.0 {
+            self.step_beats / self.beats_per_sample
+        } else {
+            0.0
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.last_step = None;
+        self.pos_beats = 0.0;
+    }
+}
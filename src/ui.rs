@@ -1,23 +1,444 @@
-use crate::DrumSynthParams;
+use crate::modulation::{MOD_SLOTS, MOD_SOURCES};
+use crate::morph::{self, MorphSlot};
+use crate::noise::Pcg32;
+use crate::presets::{preset_file_name, Preset, PresetManager};
+use crate::randomizer::{self, RandomizeScope};
+use crate::render::BounceTask;
+use crate::sequencer::{self, Pattern, Step, MAX_STEPS};
+use crate::{DrumSynth, DrumSynthParams, DrumSynthTask, LayerEqParams};
 use nih_plug::prelude::*;
 use nih_plug_vizia::vizia::prelude::*;
-use nih_plug_vizia::widgets::{ParamSlider, ParamSliderExt, ParamSliderStyle};
+use nih_plug_vizia::widgets::{ParamButton, ParamSlider, ParamSliderExt, ParamSliderStyle, RawParamEvent};
 use nih_plug_vizia::{create_vizia_editor, ViziaState, ViziaTheming};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
 use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Sequencer steps shown at a time
+const STEPS_PER_PAGE: usize = 16;
 
 #[derive(Lens)]
 struct Data {
     params: Arc<DrumSynthParams>,
+    presets: PresetManager,
+    current_preset: Option<usize>,
+    preset_name: String,
+    preset_names: Vec<String>,
+    show_browser: bool,
+
+    /// Mirrors `DrumSynthParams::random_locks` for the lock toggles
+    locked_params: Vec<String>,
+    /// Normalized values from before the last randomization
+    randomize_undo: Option<Vec<(ParamPtr, f32)>>,
+    rng: Pcg32,
+
+    /// Mirrors `SequencerParams::pattern` for the step buttons
+    pattern: Pattern,
+    step_page: usize,
+    /// The step the velocity, probability, accent and flam controls edit
+    selected_step: usize,
+
+    async_executor: AsyncExecutor<DrumSynth>,
+    bouncing: bool,
+    /// The last bounce, for revealing it in the file manager
+    bounce_file: Option<PathBuf>,
+    bounce_status: String,
+}
+
+enum PresetEvent {
+    Previous,
+    Next,
+    Load(usize),
+    SetName(String),
+    Save,
+    Import,
+    /// Sent from the background thread with the file picked for importing
+    Imported(PathBuf),
+    Export,
+    ToggleBrowser,
+}
+
+enum MorphEvent {
+    Store(MorphSlot),
+    Clear,
+}
+
+enum RandomizeEvent {
+    Randomize(RandomizeScope),
+    Undo,
+    ToggleLock(String),
+}
+
+enum SequencerEvent {
+    SelectPage(usize),
+    /// Switch a step on or off and select it
+    ToggleStep(usize),
+    SetVelocity(f32),
+    SetProbability(f32),
+    ToggleAccent,
+    ToggleFlam,
+}
+
+enum BounceEvent {
+    Bounce,
+    /// Sent from the background thread once the bounce is written
+    Finished(Option<PathBuf>),
+    Reveal,
+}
+
+/// Set a parameter through the host so it can record the change
+fn set_parameter(cx: &mut EventContext, param_ptr: ParamPtr, normalized: f32) {
+    cx.emit(RawParamEvent::BeginSetParameter(param_ptr));
+    cx.emit(RawParamEvent::SetParameterNormalized(param_ptr, normalized));
+    cx.emit(RawParamEvent::EndSetParameter(param_ptr));
+}
+
+/// A preset file dialog. The dialogs wait for the user, so they run on the background thread to
+/// keep the editor drawing meanwhile.
+pub struct PresetDialog {
+    action: DialogAction,
+}
+
+enum DialogAction {
+    /// Called with the file picked for importing
+    Import(Box<dyn FnOnce(PathBuf) + Send>),
+    /// Saved where the user picks
+    Export(Preset),
+}
+
+impl PresetDialog {
+    fn import(picked: impl FnOnce(PathBuf) + Send + 'static) -> Self {
+        Self {
+            action: DialogAction::Import(Box::new(picked)),
+        }
+    }
+
+    fn export(preset: Preset) -> Self {
+        Self {
+            action: DialogAction::Export(preset),
+        }
+    }
+
+    pub(crate) fn run(self) {
+        let dialog = rfd::AsyncFileDialog::new().add_filter("Drum Synth preset", &["json"]);
+        match self.action {
+            DialogAction::Import(picked) => {
+                if let Some(file) = pollster::block_on(dialog.pick_file()) {
+                    picked(file.path().to_owned());
+                }
+            }
+            DialogAction::Export(preset) => {
+                let dialog = dialog.set_file_name(preset_file_name(&preset.name));
+                let Some(file) = pollster::block_on(dialog.save_file()) else {
+                    return;
+                };
+
+                if let Err(err) = preset.save(file.path()) {
+                    nih_error!("Could not export '{}': {}", file.path().display(), err);
+                }
+            }
+        }
+    }
+}
+
+/// Show a file in the system's file manager, from where it can be dragged into a DAW or a
+/// sampler. The editor can't start drags to other applications itself: baseview, which hosts
+/// the window, has no drag source. Waits for the file manager on Linux, so this runs on the
+/// background thread.
+pub(crate) fn reveal_file(path: &Path) {
+    if let Err(err) = show_in_file_manager(path) {
+        nih_error!("Could not show '{}': {}", path.display(), err);
+    }
+}
+
+/// Selects the file where the file manager supports that, otherwise opens its folder
+fn show_in_file_manager(path: &Path) -> io::Result<()> {
+    if cfg!(target_os = "macos") {
+        Command::new("open").arg("-R").arg(path).spawn().map(|_| ())
+    } else if cfg!(target_os = "windows") {
+        Command::new("explorer")
+            .arg(format!("/select,{}", path.display()))
+            .spawn()
+            .map(|_| ())
+    } else {
+        // The freedesktop file manager interface selects the file, xdg-open can only open the
+        // folder it's in
+        let selected = Command::new("dbus-send")
+            .args([
+                "--session",
+                "--print-reply",
+                "--dest=org.freedesktop.FileManager1",
+                "--type=method_call",
+                "/org/freedesktop/FileManager1",
+                "org.freedesktop.FileManager1.ShowItems",
+            ])
+            .arg(format!("array:string:{}", file_url(path)))
+            .arg("string:")
+            .output()
+            .is_ok_and(|output| output.status.success());
+        if selected {
+            return Ok(());
+        }
+
+        Command::new("xdg-open")
+            .arg(path.parent().unwrap_or(path))
+            .spawn()
+            .map(|_| ())
+    }
+}
+
+/// `file://` URL for an absolute path, with everything but unreserved characters and slashes
+/// percent-encoded
+fn file_url(path: &Path) -> String {
+    let mut url = String::from("file://");
+    for byte in path.to_string_lossy().bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-._~".contains(&byte) {
+            url.push(byte as char);
+        } else {
+            url.push_str(&format!("%{byte:02X}"));
+        }
+    }
+
+    url
+}
+
+impl Data {
+    fn update_preset_names(&mut self) {
+        self.preset_names = self
+            .presets
+            .entries()
+            .iter()
+            .map(|entry| {
+                if entry.factory {
+                    format!("Factory / {}", entry.preset.name)
+                } else {
+                    entry.preset.name.clone()
+                }
+            })
+            .collect();
+    }
+
+    fn load_preset(&mut self, cx: &mut EventContext, index: Option<usize>) {
+        let Some(entry) = index.and_then(|index| self.presets.get(index)) else {
+            return;
+        };
+
+        entry.preset.apply(&self.params, |param_ptr, normalized| set_parameter(cx, param_ptr, normalized));
+
+        self.preset_name = entry.preset.name.clone();
+        self.current_preset = index;
+    }
+
+    fn save_preset(&mut self) {
+        let preset = Preset::capture(&self.preset_name, &self.params);
+        match self.presets.save_user_preset(&preset) {
+            Ok(index) => self.current_preset = Some(index),
+            Err(err) => nih_error!("Could not save preset '{}': {}", preset.name, err),
+        }
+
+        self.update_preset_names();
+    }
+
+    /// Ask for a preset file on the background thread, it comes back as `PresetEvent::Imported`
+    fn pick_import(&self, cx: &mut EventContext) {
+        let mut proxy = cx.get_proxy();
+        let dialog = PresetDialog::import(move |path| {
+            let _ = proxy.emit(PresetEvent::Imported(path));
+        });
+        self.async_executor.execute_background(DrumSynthTask::PresetDialog(dialog));
+    }
+
+    fn import_preset(&mut self, cx: &mut EventContext, path: &Path) {
+        match self.presets.import_preset(path) {
+            Ok(index) => {
+                self.update_preset_names();
+                self.load_preset(cx, Some(index));
+            }
+            Err(err) => nih_error!("Could not import '{}': {}", path.display(), err),
+        }
+    }
+
+    /// Capture the preset now, the dialog and the saving happen on the background thread
+    fn export_preset(&self) {
+        let preset = Preset::capture(&self.preset_name, &self.params);
+        self.async_executor.execute_background(DrumSynthTask::PresetDialog(PresetDialog::export(preset)));
+    }
+
+    fn randomize(&mut self, cx: &mut EventContext, scope: RandomizeScope) {
+        let values = randomizer::randomize(&self.params, scope, &self.locked_params, &mut self.rng);
+        self.randomize_undo = Some(
+            values
+                .iter()
+                // SAFETY: The pointers come straight from the params object we're holding
+                .map(|(param_ptr, _)| (*param_ptr, unsafe { param_ptr.unmodulated_normalized_value() }))
+                .collect(),
+        );
+
+        for (param_ptr, normalized) in values {
+            set_parameter(cx, param_ptr, normalized);
+        }
+    }
+
+    fn undo_randomize(&mut self, cx: &mut EventContext) {
+        for (param_ptr, normalized) in self.randomize_undo.take().unwrap_or_default() {
+            set_parameter(cx, param_ptr, normalized);
+        }
+    }
+
+    fn toggle_lock(&mut self, id: &str) {
+        if let Some(index) = self.locked_params.iter().position(|locked| locked == id) {
+            self.locked_params.remove(index);
+        } else {
+            self.locked_params.push(id.to_owned());
+        }
+
+        *self.params.random_locks.write().unwrap() = self.locked_params.clone();
+    }
+
+    fn edit_step(&mut self, index: usize, edit: impl FnOnce(&mut Step)) {
+        let sequencer_params = &self.params.sequencer_params;
+        sequencer::edit_step(sequencer_params, index, edit);
+        self.pattern = sequencer_params.pattern.read().unwrap().clone();
+    }
+
+    /// Render the patch on a background thread, the result comes back as `BounceEvent::Finished`
+    fn bounce(&mut self, cx: &mut EventContext) {
+        if self.bouncing {
+            return;
+        }
+
+        let preset = Preset::capture(&self.preset_name, &self.params);
+        let mut proxy = cx.get_proxy();
+        let task = BounceTask::new(preset, move |file| {
+            let _ = proxy.emit(BounceEvent::Finished(file));
+        });
+        self.async_executor.execute_background(DrumSynthTask::Bounce(task));
+
+        self.bouncing = true;
+        self.bounce_status = String::from("Rendering...");
+    }
+
+    fn finish_bounce(&mut self, file: Option<PathBuf>) {
+        self.bounce_status = match file.as_deref().and_then(Path::file_name) {
+            Some(file_name) => file_name.to_string_lossy().into_owned(),
+            None => String::from("Bounce failed"),
+        };
+        self.bounce_file = file;
+        self.bouncing = false;
+    }
+
+    fn reveal_bounce(&self) {
+        let Some(file) = &self.bounce_file else {
+            return;
+        };
+
+        self.async_executor.execute_background(DrumSynthTask::Reveal(file.clone()));
+    }
 }
 
-impl Model for Data {}
+impl Model for Data {
+    fn event(&mut self, cx: &mut EventContext, event: &mut Event) {
+        event.map(|preset_event, _| match preset_event {
+            PresetEvent::Previous => {
+                let index = self.presets.previous_index(self.current_preset);
+                self.load_preset(cx, index);
+            }
+            PresetEvent::Next => {
+                let index = self.presets.next_index(self.current_preset);
+                self.load_preset(cx, index);
+            }
+            PresetEvent::Load(index) => {
+                self.load_preset(cx, Some(*index));
+                self.show_browser = false;
+            }
+            PresetEvent::SetName(name) => self.preset_name = name.clone(),
+            PresetEvent::Save => self.save_preset(),
+            PresetEvent::Import => self.pick_import(cx),
+            PresetEvent::Imported(path) => self.import_preset(cx, path),
+            PresetEvent::Export => self.export_preset(),
+            PresetEvent::ToggleBrowser => {
+                // Pick up presets that were added outside of the plugin
+                if !self.show_browser {
+                    self.presets.refresh();
+                    self.update_preset_names();
+                }
+                self.show_browser = !self.show_browser;
+            }
+        });
+
+        event.map(|morph_event, _| match morph_event {
+            MorphEvent::Store(slot) => morph::store_snapshot(&self.params, *slot),
+            MorphEvent::Clear => morph::clear_snapshots(&self.params),
+        });
+
+        // Knob edits change the snapshot being played while morphing
+        event.map(|param_event: &RawParamEvent, _| {
+            if let RawParamEvent::EndSetParameter(param_ptr) = param_event {
+                morph::capture_edit(&self.params, *param_ptr);
+            }
+        });
+
+        event.map(|randomize_event, _| match randomize_event {
+            RandomizeEvent::Randomize(scope) => self.randomize(cx, *scope),
+            RandomizeEvent::Undo => self.undo_randomize(cx),
+            RandomizeEvent::ToggleLock(id) => self.toggle_lock(id),
+        });
+
+        event.map(|sequencer_event: &SequencerEvent, _| match *sequencer_event {
+            SequencerEvent::SelectPage(page) => self.step_page = page,
+            SequencerEvent::ToggleStep(index) => {
+                self.selected_step = index;
+                self.edit_step(index, |step| step.active = !step.active);
+            }
+            SequencerEvent::SetVelocity(velocity) => self.edit_step(self.selected_step, |step| step.velocity = velocity),
+            SequencerEvent::SetProbability(probability) => {
+                self.edit_step(self.selected_step, |step| step.probability = probability)
+            }
+            SequencerEvent::ToggleAccent => self.edit_step(self.selected_step, |step| step.accent = !step.accent),
+            SequencerEvent::ToggleFlam => self.edit_step(self.selected_step, |step| step.flam = !step.flam),
+        });
 
-pub(crate) fn default_editor(params: Arc<DrumSynthParams>, editor_state: Arc<ViziaState>) -> Option<Box<dyn Editor>> {
+        event.map(|bounce_event, _| match bounce_event {
+            BounceEvent::Bounce => self.bounce(cx),
+            BounceEvent::Finished(file) => self.finish_bounce(file.clone()),
+            BounceEvent::Reveal => self.reveal_bounce(),
+        });
+    }
+}
+
+pub(crate) fn default_editor(
+    params: Arc<DrumSynthParams>,
+    editor_state: Arc<ViziaState>,
+    async_executor: AsyncExecutor<DrumSynth>,
+) -> Option<Box<dyn Editor>> {
     create_vizia_editor(editor_state, ViziaTheming::Custom, move |cx, _| {
-        Data {
+        let mut data = Data {
             params: params.clone(),
-        }
-        .build(cx);
+            presets: PresetManager::new(),
+            current_preset: None,
+            preset_name: String::from("Init"),
+            preset_names: Vec::new(),
+            show_browser: false,
+            locked_params: params.random_locks.read().unwrap().clone(),
+            randomize_undo: None,
+            rng: Pcg32::new(
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|time| time.as_nanos() as u64)
+                    .unwrap_or_default(),
+            ),
+            pattern: params.sequencer_params.pattern.read().unwrap().clone(),
+            step_page: 0,
+            selected_step: 0,
+            async_executor: async_executor.clone(),
+            bouncing: false,
+            bounce_file: None,
+            bounce_status: String::new(),
+        };
+        data.update_preset_names();
+        data.build(cx);
 
         // Styling constants
         let bg_color = Color::rgb(20, 20, 20);
@@ -25,17 +446,55 @@ pub(crate) fn default_editor(params: Arc<DrumSynthParams>, editor_state: Arc<Viz
         let accent_impact = Color::rgb(233, 79, 55);
         let accent_tuning = Color::rgb(30, 136, 229);
         let accent_snare = Color::rgb(67, 160, 71);
+        let accent_humanize = Color::rgb(171, 71, 188);
+        let accent_modulation = Color::rgb(255, 179, 0);
+        let accent_sequencer = Color::rgb(0, 172, 193);
         let label_color = Color::rgb(200, 200, 200);
 
         // Root container
         VStack::new(cx, |cx| {
             // Header
-            Label::new(cx, "DRUM SYNTH")
-                .font_size(32.0)
-                .text_align(TextAlign::Center)
-                .color(Color::white())
-                .height(Percentage(8.0)) // Relative height
-                .width(Stretch(1.0));
+            HStack::new(cx, |cx| {
+                Label::new(cx, "DRUM SYNTH")
+                    .font_size(32.0)
+                    .text_align(TextAlign::Center)
+                    .color(Color::white())
+                    .width(Stretch(1.0));
+
+                // Preset bar
+                HStack::new(cx, |cx| {
+                    Button::new(cx, |cx| cx.emit(PresetEvent::Previous), |cx| Label::new(cx, "<"));
+                    Textbox::new(cx, Data::preset_name)
+                        .on_edit(|cx, text| cx.emit(PresetEvent::SetName(text)))
+                        .width(Stretch(1.0));
+                    Button::new(cx, |cx| cx.emit(PresetEvent::Next), |cx| Label::new(cx, ">"));
+                    Button::new(cx, |cx| cx.emit(PresetEvent::ToggleBrowser), |cx| Label::new(cx, "Browse"));
+                    Button::new(cx, |cx| cx.emit(PresetEvent::Save), |cx| Label::new(cx, "Save"));
+                    Button::new(cx, |cx| cx.emit(PresetEvent::Import), |cx| Label::new(cx, "Import"));
+                    Button::new(cx, |cx| cx.emit(PresetEvent::Export), |cx| Label::new(cx, "Export"));
+                })
+                .col_between(Pixels(6.0))
+                .child_space(Stretch(1.0))
+                .width(Percentage(50.0));
+            })
+            .height(Percentage(8.0)); // Relative height
+
+            // Preset browser, pushes the rest of the editor down while it's open
+            Binding::new(cx, Data::show_browser, |cx, show_browser| {
+                if show_browser.get(cx) {
+                    ScrollView::new(cx, 0.0, 0.0, false, true, |cx| {
+                        List::new(cx, Data::preset_names, |cx, index, name| {
+                            Label::new(cx, name)
+                                .font_size(14.0)
+                                .color(Color::rgb(200, 200, 200))
+                                .width(Stretch(1.0))
+                                .on_press(move |cx| cx.emit(PresetEvent::Load(index)));
+                        });
+                    })
+                    .height(Pixels(160.0))
+                    .background_color(Color::rgb(30, 30, 30));
+                }
+            });
 
             // Main Content Area
             HStack::new(cx, |cx| {
@@ -58,13 +517,94 @@ pub(crate) fn default_editor(params: Arc<DrumSynthParams>, editor_state: Arc<Viz
                     })
                     .width(Percentage(80.0))
                     .col_between(Percentage(5.0))
-                    .height(Percentage(20.0));
+                    .height(Percentage(10.0));
+
+                    // Transient shaper
+                    make_section_label(cx, "TRANSIENT");
+                    HStack::new(cx, |cx| {
+                        make_param(cx, "Atk", |p: &DrumSynthParams| &p.dynamics_params.shaper_attack);
+                        make_param(cx, "Sus", |p: &DrumSynthParams| &p.dynamics_params.shaper_sustain);
+                    }).col_between(Percentage(4.0)).height(Auto);
+
+                    // Compressor
+                    make_section_label(cx, "COMP");
+                    HStack::new(cx, |cx| {
+                        make_param(cx, "Thr", |p: &DrumSynthParams| &p.dynamics_params.comp_threshold);
+                        make_param(cx, "Rat", |p: &DrumSynthParams| &p.dynamics_params.comp_ratio);
+                    }).col_between(Percentage(4.0)).height(Auto);
+                    HStack::new(cx, |cx| {
+                        make_param(cx, "Atk", |p: &DrumSynthParams| &p.dynamics_params.comp_attack);
+                        make_param(cx, "Rel", |p: &DrumSynthParams| &p.dynamics_params.comp_release);
+                    }).col_between(Percentage(4.0)).height(Auto);
+                    HStack::new(cx, |cx| {
+                        make_param(cx, "Mkp", |p: &DrumSynthParams| &p.dynamics_params.comp_makeup);
+                        make_param(cx, "Mix", |p: &DrumSynthParams| &p.dynamics_params.comp_mix);
+                    }).col_between(Percentage(4.0)).height(Auto);
+
+                    // Limiter
+                    make_section_label(cx, "LIMITER");
+                    HStack::new(cx, |cx| {
+                        make_param(cx, "On", |p: &DrumSynthParams| &p.dynamics_params.limiter_enabled);
+                        make_param(cx, "Ceil", |p: &DrumSynthParams| &p.dynamics_params.limiter_ceiling);
+                    }).col_between(Percentage(4.0)).height(Auto);
+
+                    // Noise seeding
+                    make_section_label(cx, "NOISE");
+                    HStack::new(cx, |cx| {
+                        make_param(cx, "Mode", |p: &DrumSynthParams| &p.noise_params.seed_mode);
+                        make_param(cx, "Seed", |p: &DrumSynthParams| &p.noise_params.seed);
+                    }).col_between(Percentage(4.0)).height(Auto);
+
+                    // Oversampling
+                    make_section_label(cx, "QUALITY");
+                    HStack::new(cx, |cx| {
+                        make_param(cx, "OS", |p: &DrumSynthParams| &p.quality_params.oversampling);
+                        make_param(cx, "Offline", |p: &DrumSynthParams| &p.quality_params.offline_oversampling);
+                    }).col_between(Percentage(4.0)).height(Auto);
+
+                    // Randomize every layer at once
+                    make_section_label(cx, "RANDOMIZE");
+                    HStack::new(cx, |cx| {
+                        Button::new(cx, |cx| cx.emit(RandomizeEvent::Randomize(RandomizeScope::All)), |cx| Label::new(cx, "All"));
+                        Button::new(cx, |cx| cx.emit(RandomizeEvent::Undo), |cx| Label::new(cx, "Undo"));
+                    }).col_between(Percentage(4.0)).height(Auto);
+
+                    // A/B morph, morphing starts once both snapshots are stored
+                    make_section_label(cx, "MORPH");
+                    HStack::new(cx, |cx| {
+                        make_param(cx, "A-B", |p: &DrumSynthParams| &p.morph_params.amount);
+                        Button::new(cx, |cx| cx.emit(MorphEvent::Store(MorphSlot::A)), |cx| Label::new(cx, "A"));
+                        Button::new(cx, |cx| cx.emit(MorphEvent::Store(MorphSlot::B)), |cx| Label::new(cx, "B"));
+                        Button::new(cx, |cx| cx.emit(MorphEvent::Clear), |cx| Label::new(cx, "Clr"));
+                    }).col_between(Percentage(4.0)).height(Auto);
+
+                    // Flams, drags and rolls from a single note
+                    make_section_label(cx, "RATCHET");
+                    HStack::new(cx, |cx| {
+                        make_param(cx, "Mode", |p: &DrumSynthParams| &p.ratchet_params.mode);
+                        make_param(cx, "Time", |p: &DrumSynthParams| &p.ratchet_params.grace_time);
+                        make_param(cx, "Lvl", |p: &DrumSynthParams| &p.ratchet_params.grace_level);
+                    }).col_between(Percentage(4.0)).height(Auto);
+                    HStack::new(cx, |cx| {
+                        make_param(cx, "Rate", |p: &DrumSynthParams| &p.ratchet_params.roll_rate);
+                        make_param(cx, "Hits", |p: &DrumSynthParams| &p.ratchet_params.roll_count);
+                        make_param(cx, "Ramp", |p: &DrumSynthParams| &p.ratchet_params.roll_ramp);
+                    }).col_between(Percentage(4.0)).height(Auto);
+
+                    // Render the patch to a file, which gets dragged into a DAW or a sampler from
+                    // the file manager
+                    make_section_label(cx, "BOUNCE");
+                    HStack::new(cx, |cx| {
+                        Button::new(cx, |cx| cx.emit(BounceEvent::Bounce), |cx| Label::new(cx, "Hit"));
+                        Button::new(cx, |cx| cx.emit(BounceEvent::Reveal), |cx| Label::new(cx, "Show in Folder"));
+                    }).col_between(Percentage(4.0)).height(Auto);
+                    Label::new(cx, Data::bounce_status).font_size(12.0).color(label_color).width(Stretch(1.0));
                 })
                 .width(Percentage(20.0)) // 20% width
                 .background_color(panel_color)
                 .border_radius(Percentage(2.0))
                 .child_space(Percentage(2.0))
-                .row_between(Percentage(5.0));
+                .row_between(Percentage(2.0));
 
                 // --- LAYERS (Right Column) ---
                 VStack::new(cx, |cx| {
@@ -75,7 +615,10 @@ pub(crate) fn default_editor(params: Arc<DrumSynthParams>, editor_state: Arc<Viz
                         Element::new(cx).width(Percentage(1.0)).background_color(accent_impact);
 
                         // Label
-                        Label::new(cx, "IMPACT").font_size(20.0).color(accent_impact).width(Percentage(12.0));
+                        VStack::new(cx, |cx| {
+                            Label::new(cx, "IMPACT").font_size(20.0).color(accent_impact);
+                            Button::new(cx, |cx| cx.emit(RandomizeEvent::Randomize(RandomizeScope::Layer("Impact"))), |cx| Label::new(cx, "Rnd"));
+                        }).row_between(Pixels(4.0)).width(Percentage(12.0));
 
                         // Spacer
                         Element::new(cx).width(Stretch(1.0));
@@ -86,24 +629,18 @@ pub(crate) fn default_editor(params: Arc<DrumSynthParams>, editor_state: Arc<Viz
                             make_param(cx, "Hld", |p: &DrumSynthParams| &p.impact_params.hold);
                             make_param(cx, "Dec", |p: &DrumSynthParams| &p.impact_params.decay);
                             make_param(cx, "Rel", |p: &DrumSynthParams| &p.impact_params.release);
+                            make_param(cx, "ACv", |p: &DrumSynthParams| &p.impact_params.attack_curve);
+                            make_param(cx, "DCv", |p: &DrumSynthParams| &p.impact_params.decay_curve);
+                            make_param(cx, "RCv", |p: &DrumSynthParams| &p.impact_params.release_curve);
                             make_param(cx, "Lvl", |p: &DrumSynthParams| &p.impact_params.level);
-                        }).col_between(Percentage(2.0)).width(Percentage(45.0));
+                            make_param(cx, "Clr", |p: &DrumSynthParams| &p.impact_params.noise_color);
+                        }).col_between(Percentage(2.0)).width(Percentage(30.0));
 
                         // Spacer
                         Element::new(cx).width(Stretch(1.0));
 
                         // EQ
-                        HStack::new(cx, |cx| {
-                            Label::new(cx, "EQ").font_size(12.0).color(Color::gray()).width(Percentage(15.0));
-                            make_param(cx, "F", |p: &DrumSynthParams| &p.impact_params.eq_freq);
-                            make_param(cx, "G", |p: &DrumSynthParams| &p.impact_params.eq_gain);
-                            make_param(cx, "Q", |p: &DrumSynthParams| &p.impact_params.eq_q);
-                        })
-                        .background_color(Color::rgb(42, 42, 42))
-                        .border_radius(Percentage(5.0))
-                        .child_space(Percentage(2.0))
-                        .col_between(Percentage(2.0))
-                        .width(Percentage(20.0));
+                        make_eq(cx, |p: &DrumSynthParams| &p.impact_params.eq);
 
                     })
                     .height(Stretch(1.0)) // Distribute height equally
@@ -118,7 +655,10 @@ pub(crate) fn default_editor(params: Arc<DrumSynthParams>, editor_state: Arc<Viz
                         Element::new(cx).width(Percentage(1.0)).background_color(accent_tuning);
 
                         // Label
-                        Label::new(cx, "TUNING").font_size(20.0).color(accent_tuning).width(Percentage(12.0));
+                        VStack::new(cx, |cx| {
+                            Label::new(cx, "TUNING").font_size(20.0).color(accent_tuning);
+                            Button::new(cx, |cx| cx.emit(RandomizeEvent::Randomize(RandomizeScope::Layer("Tuning"))), |cx| Label::new(cx, "Rnd"));
+                        }).row_between(Pixels(4.0)).width(Percentage(12.0));
 
                         // Spacer
                         Element::new(cx).width(Stretch(1.0));
@@ -128,24 +668,19 @@ pub(crate) fn default_editor(params: Arc<DrumSynthParams>, editor_state: Arc<Viz
                             make_param(cx, "Ten", |p: &DrumSynthParams| &p.tuning_params.delay_samples);
                             make_param(cx, "Sus", |p: &DrumSynthParams| &p.tuning_params.feedback);
                             make_param(cx, "Dmp", |p: &DrumSynthParams| &p.tuning_params.damping);
+                            make_param(cx, "Flt", |p: &DrumSynthParams| &p.tuning_params.loop_filter);
+                            make_param(cx, "Stf", |p: &DrumSynthParams| &p.tuning_params.stiffness);
                             make_param(cx, "Lvl", |p: &DrumSynthParams| &p.tuning_params.level);
+                            make_param(cx, "Pch", |p: &DrumSynthParams| &p.tuning_params.pitch_amount);
+                            make_param(cx, "PDc", |p: &DrumSynthParams| &p.tuning_params.pitch_decay);
+                            make_param(cx, "Crv", |p: &DrumSynthParams| &p.tuning_params.pitch_curve);
                         }).col_between(Percentage(2.0)).width(Percentage(36.0));
                         
                         // Spacer
                         Element::new(cx).width(Stretch(1.0));
 
                         // EQ
-                        HStack::new(cx, |cx| {
-                            Label::new(cx, "EQ").font_size(12.0).color(Color::gray()).width(Percentage(15.0));
-                            make_param(cx, "F", |p: &DrumSynthParams| &p.tuning_params.eq_freq);
-                            make_param(cx, "G", |p: &DrumSynthParams| &p.tuning_params.eq_gain);
-                            make_param(cx, "Q", |p: &DrumSynthParams| &p.tuning_params.eq_q);
-                        })
-                        .background_color(Color::rgb(42, 42, 42))
-                        .border_radius(Percentage(5.0))
-                        .child_space(Percentage(2.0))
-                        .col_between(Percentage(2.0))
-                        .width(Percentage(20.0));
+                        make_eq(cx, |p: &DrumSynthParams| &p.tuning_params.eq);
 
                     })
                     .height(Stretch(1.0))
@@ -160,33 +695,34 @@ pub(crate) fn default_editor(params: Arc<DrumSynthParams>, editor_state: Arc<Viz
                         Element::new(cx).width(Percentage(1.0)).background_color(accent_snare);
 
                         // Label
-                        Label::new(cx, "SNARE").font_size(20.0).color(accent_snare).width(Percentage(12.0));
+                        VStack::new(cx, |cx| {
+                            Label::new(cx, "SNARE").font_size(20.0).color(accent_snare);
+                            Button::new(cx, |cx| cx.emit(RandomizeEvent::Randomize(RandomizeScope::Layer("Snare"))), |cx| Label::new(cx, "Rnd"));
+                        }).row_between(Pixels(4.0)).width(Percentage(12.0));
 
                         // Spacer
                         Element::new(cx).width(Stretch(1.0));
 
                         // Controls
                         HStack::new(cx, |cx| {
+                            make_param(cx, "Dly", |p: &DrumSynthParams| &p.snare_params.delay);
                             make_param(cx, "Atk", |p: &DrumSynthParams| &p.snare_params.attack);
+                            make_param(cx, "Hld", |p: &DrumSynthParams| &p.snare_params.hold);
                             make_param(cx, "Dec", |p: &DrumSynthParams| &p.snare_params.decay);
+                            make_param(cx, "Sus", |p: &DrumSynthParams| &p.snare_params.sustain);
+                            make_param(cx, "Rel", |p: &DrumSynthParams| &p.snare_params.release);
+                            make_param(cx, "ACv", |p: &DrumSynthParams| &p.snare_params.attack_curve);
+                            make_param(cx, "DCv", |p: &DrumSynthParams| &p.snare_params.decay_curve);
+                            make_param(cx, "RCv", |p: &DrumSynthParams| &p.snare_params.release_curve);
                             make_param(cx, "Lvl", |p: &DrumSynthParams| &p.snare_params.level);
-                        }).col_between(Percentage(2.0)).width(Percentage(27.0));
+                            make_param(cx, "Clr", |p: &DrumSynthParams| &p.snare_params.noise_color);
+                        }).col_between(Percentage(2.0)).width(Percentage(36.0));
 
                         // Spacer
-                        Element::new(cx).width(Stretch(2.0));
+                        Element::new(cx).width(Stretch(1.0));
 
                         // EQ
-                        HStack::new(cx, |cx| {
-                            Label::new(cx, "EQ").font_size(12.0).color(Color::gray()).width(Percentage(15.0));
-                            make_param(cx, "F", |p: &DrumSynthParams| &p.snare_params.eq_freq);
-                            make_param(cx, "G", |p: &DrumSynthParams| &p.snare_params.eq_gain);
-                            make_param(cx, "Q", |p: &DrumSynthParams| &p.snare_params.eq_q);
-                        })
-                        .background_color(Color::rgb(42, 42, 42))
-                        .border_radius(Percentage(5.0))
-                        .child_space(Percentage(2.0))
-                        .col_between(Percentage(2.0))
-                        .width(Percentage(20.0));
+                        make_eq(cx, |p: &DrumSynthParams| &p.snare_params.eq);
 
                     })
                     .height(Stretch(1.0))
@@ -195,6 +731,162 @@ pub(crate) fn default_editor(params: Arc<DrumSynthParams>, editor_state: Arc<Viz
                     .col_between(Percentage(2.0))
                     .child_space(Percentage(2.0));
 
+                    // HUMANIZE
+                    HStack::new(cx, |cx| {
+                        // Accent strip
+                        Element::new(cx).width(Percentage(1.0)).background_color(accent_humanize);
+
+                        // Label
+                        Label::new(cx, "HUMANIZE").font_size(20.0).color(accent_humanize).width(Percentage(12.0));
+
+                        // Spacer
+                        Element::new(cx).width(Stretch(1.0));
+
+                        // Controls
+                        HStack::new(cx, |cx| {
+                            make_param(cx, "Ten", |p: &DrumSynthParams| &p.humanize_params.tension);
+                            make_param(cx, "Dmp", |p: &DrumSynthParams| &p.humanize_params.damping);
+                            make_param(cx, "Lvl", |p: &DrumSynthParams| &p.humanize_params.level);
+                            make_param(cx, "Tone", |p: &DrumSynthParams| &p.humanize_params.tone);
+                            make_param(cx, "Time", |p: &DrumSynthParams| &p.humanize_params.timing);
+                            make_param(cx, "RR", |p: &DrumSynthParams| &p.humanize_params.round_robin);
+                        }).col_between(Percentage(2.0)).width(Percentage(60.0));
+
+                        // Spacer
+                        Element::new(cx).width(Stretch(1.0));
+                    })
+                    .height(Percentage(14.0))
+                    .background_color(Color::rgb(37, 37, 37))
+                    .border_radius(Percentage(1.0))
+                    .col_between(Percentage(2.0))
+                    .child_space(Percentage(2.0));
+
+                    // MODULATION SOURCES
+                    HStack::new(cx, |cx| {
+                        // Accent strip
+                        Element::new(cx).width(Percentage(1.0)).background_color(accent_modulation);
+
+                        // Label
+                        Label::new(cx, "MOD SRC").font_size(20.0).color(accent_modulation).width(Percentage(12.0));
+
+                        // Controls, one block per LFO and envelope
+                        for i in 0..MOD_SOURCES {
+                            HStack::new(cx, move |cx| {
+                                make_param(cx, "Shp", move |p: &DrumSynthParams| &p.lfo_params[i].shape);
+                                make_param(cx, "Rate", move |p: &DrumSynthParams| &p.lfo_params[i].rate);
+                                make_param(cx, "Sync", move |p: &DrumSynthParams| &p.lfo_params[i].sync);
+                                make_param(cx, "Div", move |p: &DrumSynthParams| &p.lfo_params[i].division);
+                                make_param(cx, "Rtg", move |p: &DrumSynthParams| &p.lfo_params[i].retrigger);
+                            }).col_between(Percentage(2.0)).width(Stretch(5.0));
+                        }
+                        for i in 0..MOD_SOURCES {
+                            HStack::new(cx, move |cx| {
+                                make_param(cx, "Atk", move |p: &DrumSynthParams| &p.mod_envelope_params[i].attack);
+                                make_param(cx, "Hld", move |p: &DrumSynthParams| &p.mod_envelope_params[i].hold);
+                                make_param(cx, "Dec", move |p: &DrumSynthParams| &p.mod_envelope_params[i].decay);
+                            }).col_between(Percentage(2.0)).width(Stretch(3.0));
+                        }
+                    })
+                    .height(Percentage(14.0))
+                    .background_color(Color::rgb(37, 37, 37))
+                    .border_radius(Percentage(1.0))
+                    .col_between(Percentage(2.0))
+                    .child_space(Percentage(2.0));
+
+                    // MOD MATRIX
+                    HStack::new(cx, |cx| {
+                        // Accent strip
+                        Element::new(cx).width(Percentage(1.0)).background_color(accent_modulation);
+
+                        // Label
+                        Label::new(cx, "MATRIX").font_size(20.0).color(accent_modulation).width(Percentage(12.0));
+
+                        // Controls, one block per routing
+                        for i in 0..MOD_SLOTS {
+                            HStack::new(cx, move |cx| {
+                                make_param(cx, "Src", move |p: &DrumSynthParams| &p.mod_slot_params[i].source);
+                                make_param(cx, "Dst", move |p: &DrumSynthParams| &p.mod_slot_params[i].destination);
+                                make_param(cx, "Amt", move |p: &DrumSynthParams| &p.mod_slot_params[i].depth);
+                            }).col_between(Percentage(2.0)).width(Stretch(1.0));
+                        }
+                    })
+                    .height(Percentage(14.0))
+                    .background_color(Color::rgb(37, 37, 37))
+                    .border_radius(Percentage(1.0))
+                    .col_between(Percentage(2.0))
+                    .child_space(Percentage(2.0));
+
+                    // SEQUENCER
+                    HStack::new(cx, |cx| {
+                        // Accent strip
+                        Element::new(cx).width(Percentage(1.0)).background_color(accent_sequencer);
+
+                        // Label
+                        Label::new(cx, "SEQUENCER").font_size(20.0).color(accent_sequencer).width(Percentage(12.0));
+
+                        VStack::new(cx, |cx| {
+                            // Settings
+                            HStack::new(cx, |cx| {
+                                make_param(cx, "On", |p: &DrumSynthParams| &p.sequencer_params.enabled);
+                                make_param(cx, "Len", |p: &DrumSynthParams| &p.sequencer_params.length);
+                                make_param(cx, "Rate", |p: &DrumSynthParams| &p.sequencer_params.rate);
+                                make_param(cx, "BPM", |p: &DrumSynthParams| &p.sequencer_params.tempo);
+                                make_param(cx, "Acc", |p: &DrumSynthParams| &p.sequencer_params.accent);
+                                make_param(cx, "Flam", |p: &DrumSynthParams| &p.sequencer_params.flam_time);
+                                make_param(cx, "VSns", |p: &DrumSynthParams| &p.velocity_sensitivity);
+                            }).col_between(Percentage(2.0)).height(Auto);
+
+                            // Steps, one page at a time
+                            HStack::new(cx, move |cx| {
+                                for page in 0..MAX_STEPS / STEPS_PER_PAGE {
+                                    Button::new(cx, move |cx| cx.emit(SequencerEvent::SelectPage(page)), move |cx| {
+                                        Label::new(cx, format!("P{}", page + 1))
+                                    });
+                                }
+
+                                Binding::new(cx, Data::step_page, move |cx, page| {
+                                    let first_step = page.get(cx) * STEPS_PER_PAGE;
+                                    HStack::new(cx, move |cx| {
+                                        for index in first_step..first_step + STEPS_PER_PAGE {
+                                            make_step(cx, index, accent_sequencer);
+                                        }
+                                    }).col_between(Pixels(3.0)).height(Auto);
+                                });
+                            }).col_between(Pixels(6.0)).height(Auto);
+
+                            // The selected step's settings
+                            Binding::new(cx, Data::selected_step, |cx, selected_step| {
+                                let index = selected_step.get(cx);
+                                HStack::new(cx, move |cx| {
+                                    Label::new(cx, format!("Step {}", index + 1)).font_size(12.0).color(label_color);
+
+                                    Label::new(cx, "Vel").font_size(12.0).color(label_color);
+                                    Slider::new(cx, Data::pattern.map(move |pattern| pattern.step(index).velocity))
+                                        .on_changing(|cx, velocity| cx.emit(SequencerEvent::SetVelocity(velocity)))
+                                        .width(Stretch(1.0));
+
+                                    Label::new(cx, "Prob").font_size(12.0).color(label_color);
+                                    Slider::new(cx, Data::pattern.map(move |pattern| pattern.step(index).probability))
+                                        .on_changing(|cx, probability| cx.emit(SequencerEvent::SetProbability(probability)))
+                                        .width(Stretch(1.0));
+
+                                    Label::new(cx, "Acc").font_size(12.0).color(label_color);
+                                    Checkbox::new(cx, Data::pattern.map(move |pattern| pattern.step(index).accent))
+                                        .on_toggle(|cx| cx.emit(SequencerEvent::ToggleAccent));
+
+                                    Label::new(cx, "Flam").font_size(12.0).color(label_color);
+                                    Checkbox::new(cx, Data::pattern.map(move |pattern| pattern.step(index).flam))
+                                        .on_toggle(|cx| cx.emit(SequencerEvent::ToggleFlam));
+                                }).col_between(Pixels(6.0)).height(Auto);
+                            });
+                        }).row_between(Pixels(6.0)).width(Stretch(1.0));
+                    })
+                    .height(Percentage(18.0))
+                    .background_color(Color::rgb(37, 37, 37))
+                    .border_radius(Percentage(1.0))
+                    .col_between(Percentage(2.0))
+                    .child_space(Percentage(2.0));
+
                 })
                 .width(Percentage(75.0)) // 75% width for layers
                 .row_between(Percentage(2.0));
@@ -208,12 +900,25 @@ pub(crate) fn default_editor(params: Arc<DrumSynthParams>, editor_state: Arc<Viz
 }
 
 // Helper to create a parameter control block
-fn make_param<F>(cx: &mut Context, label: &str, map_fn: F)
+fn make_param<P, F>(cx: &mut Context, label: &str, map_fn: F)
 where
-    F: Fn(&DrumSynthParams) -> &FloatParam + Copy + 'static,
+    P: Param + 'static,
+    F: Fn(&DrumSynthParams) -> &P + Copy + 'static,
 {
+    // Parameters the randomizer touches get a lock toggle next to their label
+    let params = Data::params.get(cx);
+    let lock_id = randomizer::randomizable_id(&params, map_fn(&params).as_ptr());
+
     VStack::new(cx, move |cx| {
-        Label::new(cx, label).font_size(12.0).color(Color::rgb(200, 200, 200)).text_align(TextAlign::Center);
+        HStack::new(cx, |cx| {
+            Label::new(cx, label).font_size(12.0).color(Color::rgb(200, 200, 200)).text_align(TextAlign::Center);
+            if let Some(id) = lock_id {
+                let toggled_id = id.clone();
+                Checkbox::new(cx, Data::locked_params.map(move |locked| locked.contains(&id)))
+                    .on_toggle(move |cx| cx.emit(RandomizeEvent::ToggleLock(toggled_id.clone())))
+                    .size(Pixels(10.0));
+            }
+        }).col_between(Pixels(2.0)).height(Auto);
         ParamSlider::new(cx, Data::params, move |params| map_fn(params))
             .set_style(ParamSliderStyle::CurrentStep { even: true })
             .width(Stretch(1.0)); // Ensure slider stretches to fill container
@@ -221,3 +926,91 @@ where
     .width(Stretch(1.0)) // Stretch to fill available space in the control block
     .col_between(Percentage(5.0));
 }
+
+// Helper to create a sequencer step button, lit while the step is on and brighter when accented
+fn make_step(cx: &mut Context, index: usize, accent_color: Color) {
+    Button::new(cx, move |cx| cx.emit(SequencerEvent::ToggleStep(index)), move |cx| {
+        Label::new(cx, (index + 1).to_string()).font_size(10.0)
+    })
+    .background_color(Data::pattern.map(move |pattern| {
+        let step = pattern.step(index);
+        match (step.active, step.accent) {
+            (true, true) => Color::rgb(128, 222, 234),
+            (true, false) => accent_color,
+            (false, _) => Color::rgb(55, 55, 55),
+        }
+    }))
+    .border_width(Pixels(1.0))
+    .border_color(Data::selected_step.map(move |selected| {
+        if *selected == index {
+            Color::white()
+        } else {
+            Color::transparent()
+        }
+    }))
+    .width(Stretch(1.0));
+}
+
+// Helper to create a layer's EQ block, one column per band
+fn make_eq<F>(cx: &mut Context, eq: F)
+where
+    F: Fn(&DrumSynthParams) -> &LayerEqParams + Copy + 'static,
+{
+    HStack::new(cx, move |cx| {
+        Label::new(cx, "EQ").font_size(12.0).color(Color::gray()).width(Percentage(6.0));
+
+        make_eq_band(cx, move |p: &DrumSynthParams| &eq(p).low_cut_enabled, move |cx| {
+            make_param(cx, "F", move |p: &DrumSynthParams| &eq(p).low_cut_freq);
+        });
+        make_eq_band(cx, move |p: &DrumSynthParams| &eq(p).low_shelf_enabled, move |cx| {
+            make_param(cx, "F", move |p: &DrumSynthParams| &eq(p).low_shelf_freq);
+            make_param(cx, "G", move |p: &DrumSynthParams| &eq(p).low_shelf_gain);
+        });
+        make_eq_band(cx, move |p: &DrumSynthParams| &eq(p).peak_enabled, move |cx| {
+            make_param(cx, "F", move |p: &DrumSynthParams| &eq(p).peak_freq);
+            make_param(cx, "G", move |p: &DrumSynthParams| &eq(p).peak_gain);
+            make_param(cx, "Q", move |p: &DrumSynthParams| &eq(p).peak_q);
+        });
+        make_eq_band(cx, move |p: &DrumSynthParams| &eq(p).peak2_enabled, move |cx| {
+            make_param(cx, "F", move |p: &DrumSynthParams| &eq(p).peak2_freq);
+            make_param(cx, "G", move |p: &DrumSynthParams| &eq(p).peak2_gain);
+            make_param(cx, "Q", move |p: &DrumSynthParams| &eq(p).peak2_q);
+        });
+        make_eq_band(cx, move |p: &DrumSynthParams| &eq(p).high_shelf_enabled, move |cx| {
+            make_param(cx, "F", move |p: &DrumSynthParams| &eq(p).high_shelf_freq);
+            make_param(cx, "G", move |p: &DrumSynthParams| &eq(p).high_shelf_gain);
+        });
+        make_eq_band(cx, move |p: &DrumSynthParams| &eq(p).high_cut_enabled, move |cx| {
+            make_param(cx, "F", move |p: &DrumSynthParams| &eq(p).high_cut_freq);
+        });
+    })
+    .background_color(Color::rgb(42, 42, 42))
+    .border_radius(Percentage(5.0))
+    .child_space(Percentage(2.0))
+    .col_between(Percentage(2.0))
+    .width(Percentage(50.0));
+}
+
+// Helper to create a single EQ band: its enable switch with the band's controls below
+fn make_eq_band<F, C>(cx: &mut Context, enabled: F, controls: C)
+where
+    F: Fn(&DrumSynthParams) -> &BoolParam + Copy + 'static,
+    C: FnOnce(&mut Context) + 'static,
+{
+    VStack::new(cx, move |cx| {
+        ParamButton::new(cx, Data::params, move |params| enabled(params))
+            .width(Stretch(1.0));
+        controls(cx);
+    })
+    .width(Stretch(1.0))
+    .row_between(Pixels(2.0));
+}
+
+// Helper to create a small sub-heading inside a column
+fn make_section_label(cx: &mut Context, label: &str) {
+    Label::new(cx, label)
+        .font_size(14.0)
+        .color(Color::gray())
+        .text_align(TextAlign::Center)
+        .width(Stretch(1.0));
+}
@@ -0,0 +1,90 @@
+//! Playing `DrumEngine` through its public API, the way an embedding application does
+
+use drum_synth::{DrumEngine, Patch, PreparedPatch};
+
+const SAMPLE_RATE: f32 = 48000.0;
+
+/// Samples between the hits in `render_hits()`
+const HIT_SAMPLES: usize = 4800;
+
+fn engine() -> DrumEngine {
+    let mut engine = DrumEngine::new(SAMPLE_RATE);
+    engine.set_patch(&Patch::factory("Deep Tom").unwrap());
+    engine
+}
+
+fn peak(samples: &[f32]) -> f32 {
+    samples
+        .iter()
+        .fold(0.0f32, |peak, sample| peak.max(sample.abs()))
+}
+
+/// A few hits from an engine seeded with `seed`
+fn render_hits(seed: u64) -> Vec<f32> {
+    let mut engine = engine();
+    engine.seed(seed);
+
+    let mut output = vec![0.0; 3 * HIT_SAMPLES];
+    for hit in output.chunks_mut(HIT_SAMPLES) {
+        engine.trigger(45, 0.8);
+        engine.render(hit);
+        engine.release(45);
+    }
+    output
+}
+
+#[test]
+fn hit_rings_out_after_release() {
+    let mut engine = engine();
+    let mut buffer = [0.0; 512];
+
+    engine.trigger(45, 0.8);
+    engine.render(&mut buffer);
+    assert!(engine.is_active());
+    assert!(peak(&buffer) > 1e-3, "the hit is silent");
+
+    engine.release(45);
+    let mut rendered = 0;
+    while engine.is_active() {
+        engine.render(&mut buffer);
+        rendered += buffer.len();
+        assert!(
+            rendered < 10 * SAMPLE_RATE as usize,
+            "still ringing after 10 seconds"
+        );
+    }
+
+    // Nothing but silence until the next hit
+    engine.render(&mut buffer);
+    assert!(buffer.iter().all(|&sample| sample == 0.0));
+
+    engine.trigger(45, 0.8);
+    engine.render(&mut buffer);
+    assert!(peak(&buffer) > 1e-3, "the next hit is silent");
+}
+
+#[test]
+fn same_seed_same_output() {
+    assert_eq!(render_hits(7), render_hits(7));
+    assert_ne!(render_hits(7), render_hits(8));
+}
+
+#[test]
+fn prepared_patch_plays_like_the_patch() {
+    let patch = Patch::factory("Tight Funk Snare").unwrap();
+    let prepared = PreparedPatch::new(&patch);
+
+    let render = |set: &dyn Fn(&mut DrumEngine)| {
+        let mut engine = engine();
+        engine.seed(3);
+        set(&mut engine);
+
+        let mut output = vec![0.0; HIT_SAMPLES];
+        engine.trigger(45, 0.8);
+        engine.render(&mut output);
+        output
+    };
+    let expected = render(&|engine| engine.set_patch(&patch));
+    assert_eq!(render(&|engine| engine.set_prepared_patch(&prepared)), expected);
+    assert_ne!(render(&|_| ()), expected);
+}